@@ -0,0 +1,133 @@
+//! The helpers generated by `#[coll(option(active_record))]`.
+
+use std::collections::{BTreeMap, HashMap};
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+
+use crate::{finders, schema, CompoundIndex, SingleFieldIndex};
+
+/// `find_by_<field>` for the field of every unique single field finder but the `_id`, fetching
+/// through the generated `by_` finder.
+fn finders(
+    coll_struct_id: &syn::Ident,
+    single_indexes: &HashMap<String, SingleFieldIndex>,
+    compound_indexes: &HashMap<String, CompoundIndex>,
+    rename_all: Option<&str>,
+) -> Vec<TokenStream> {
+    let plans = finders::plans(single_indexes, compound_indexes);
+
+    // By field, so a field indexed twice gets one finder and the output is stable.
+    let fields = plans
+        .iter()
+        .filter(|plan| plan.unique && plan.fields.len() == 1)
+        .map(|plan| {
+            let field = plan.fields[0];
+            (field.ident.as_ref().unwrap().unraw().to_string(), plan)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    fields
+        .into_iter()
+        .filter_map(|(ident, plan)| {
+            let field = plan.fields[0];
+            let name =
+                schema::field_name(field, schema::serde_attrs(&field.attrs).rename, rename_all);
+            if name == "_id" {
+                return None;
+            }
+
+            let method = format_ident!("find_by_{}", ident);
+            let by = format_ident!("{}", plan.method);
+            let arg = finders::arg_type(field);
+            let doc = format!("Fetches the document by its unique `{}`.", ident);
+
+            Some(quote! {
+                #[doc = #doc]
+                pub async fn #method<C>(
+                    coll: &C,
+                    value: #arg,
+                ) -> Result<Option<Self>, ::collection::Error>
+                where
+                    C: ::collection::Collection<
+                        Document = Self,
+                        Internal: ::collection::mongo::Source<Self>,
+                    >,
+                {
+                    ::collection::Collection::fetch(coll, #coll_struct_id::#by(value)).await
+                }
+            })
+        })
+        .collect()
+}
+
+pub fn derive(
+    source_id: &syn::Ident,
+    coll_struct_id: &syn::Ident,
+    single_indexes: &HashMap<String, SingleFieldIndex>,
+    compound_indexes: &HashMap<String, CompoundIndex>,
+    rename_all: Option<&str>,
+) -> TokenStream {
+    let finders = finders(coll_struct_id, single_indexes, compound_indexes, rename_all);
+
+    quote! {
+        impl #source_id {
+            /// Inserts the document into `coll`, see `WritableCollection::insert`.
+            pub async fn insert<C>(
+                &self,
+                coll: &C,
+            ) -> ::collection::mongo::WriteResult<C, ::collection::Id<Self>>
+            where
+                C: ::collection::mongo::WritableCollection<Document = Self>,
+            {
+                ::collection::mongo::WritableCollection::insert(coll, self).await
+            }
+
+            /// Replaces the document in `coll` at the version it was read at, or inserts it if it
+            /// isn't stored yet, see `WritableCollection::replace` and `insert`. Fails with
+            /// `WriteError::ConcurrentModification` if it was written by someone else since.
+            pub async fn save<C>(&self, coll: &C) -> ::collection::mongo::WriteResult<C, ()>
+            where
+                C: ::collection::mongo::WritableCollection<Document = Self>,
+            {
+                let raw = ::collection::migrate::encode(self)?;
+                let stored = raw
+                    .get("_id")
+                    .is_some_and(|id| *id != ::mongodb::bson::Bson::Null);
+                if stored && ::collection::mongo::WritableCollection::replace(coll, self).await? {
+                    return Ok(());
+                }
+                ::collection::mongo::WritableCollection::insert(coll, self).await?;
+                Ok(())
+            }
+
+            /// Deletes the document from `coll` by its `_id`, returning whether it was found.
+            pub async fn delete<C>(&self, coll: &C) -> ::collection::mongo::WriteResult<C, bool>
+            where
+                C: ::collection::mongo::WritableCollection<Document = Self>,
+            {
+                let raw = ::collection::migrate::encode(self)?;
+                let id = raw.get("_id").cloned().unwrap_or(::mongodb::bson::Bson::Null);
+                let filter = ::mongodb::bson::doc! { "_id": id };
+                ::collection::mongo::WritableCollection::delete(coll, filter).await
+            }
+
+            pub async fn find_by_id<C, K>(
+                coll: &C,
+                id: K,
+            ) -> Result<Option<Self>, ::collection::Error>
+            where
+                C: ::collection::Collection<
+                    Document = Self,
+                    Internal: ::collection::mongo::Source<Self>,
+                >,
+                K: ::collection::id::IntoId<Self> + Send,
+            {
+                ::collection::Collection::fetch(coll, ::collection::fetchers::ById(id)).await
+            }
+
+            #(#finders)*
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! Grammar of the `#[coll(...)]` attribute language.
+//!
+//! An attribute is a comma separated list of arguments, each of which is one of
+//!
+//! - a flag: `unique`
+//! - an assignment: `type = Text`, `version = 2`, `regex = "^a"`, `offset = -1`
+//! - a nested list: `index(...)`, parsed lazily so lists may also hold expressions like `1..=64`
+//! - a named selection: `single email`, `pub UserColl users`
+
+use proc_macro2::{Delimiter, Group, Span};
+use proc_macro_error::{abort, emit_error};
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{Attribute, Expr, Ident, Lit, LitStr, Token, Visibility};
+
+pub enum Value {
+    Flag,
+    Name(String, Span),
+    Assign(Box<Expr>),
+    List(Group),
+}
+
+pub struct Arg {
+    pub vis: Visibility,
+    pub key: Ident,
+    pub value: Value,
+}
+
+pub struct Args(pub Vec<Arg>);
+
+impl Parse for Arg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let vis = input.parse()?;
+        let key = input.call(Ident::parse_any)?;
+
+        let value = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            Value::Assign(Box::new(input.parse()?))
+        } else if input.peek(syn::token::Paren) {
+            let group: Group = input.parse()?;
+            Value::List(group)
+        } else if input.peek(Token![:]) || input.peek(LitStr) || input.peek(Ident::peek_any) {
+            let _ = input.parse::<Option<Token![:]>>()?;
+
+            if input.peek(LitStr) {
+                let lit: LitStr = input.parse()?;
+                Value::Name(lit.value(), lit.span())
+            } else {
+                let id = input.call(Ident::parse_any)?;
+                Value::Name(id.to_string(), id.span())
+            }
+        } else {
+            Value::Flag
+        };
+
+        Ok(Self { vis, key, value })
+    }
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let args = Punctuated::<Arg, Token![,]>::parse_terminated(input)?;
+        Ok(Self(args.into_iter().collect()))
+    }
+}
+
+impl Args {
+    /// Parses the arguments of every `#[coll(...)]` among `attrs`.
+    pub fn from_attrs(attrs: &[Attribute]) -> Self {
+        let mut out = Vec::new();
+
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("coll")) {
+            if attr.tokens.is_empty() {
+                abort!(
+                    attr.span(),
+                    "coll attribute requires arguments #[coll(...)]"
+                );
+            }
+
+            match attr.parse_args::<Args>() {
+                Ok(args) => out.extend(args.0),
+                Err(e) => abort!(e.span(), "{}", e),
+            }
+        }
+
+        Self(out)
+    }
+}
+
+impl Arg {
+    pub fn name(&self) -> String {
+        self.key.to_string()
+    }
+
+    pub fn span(&self) -> Span {
+        match &self.value {
+            Value::Flag => self.key.span(),
+            Value::Name(_, span) => self.key.span().join(*span).unwrap_or(self.key.span()),
+            Value::Assign(expr) => self.key.span().join(expr.span()).unwrap_or(self.key.span()),
+            Value::List(group) => self
+                .key
+                .span()
+                .join(group.span())
+                .unwrap_or(self.key.span()),
+        }
+    }
+
+    pub fn no_vis(&self) {
+        if !matches!(self.vis, Visibility::Inherited) {
+            emit_error!(
+                self.vis.span(),
+                "Visibility is only allowed on the collection declaration"
+            );
+        }
+    }
+
+    /// A flag, or a flag explicitly assigned a bool.
+    pub fn flag(&self) -> bool {
+        let expr = match &self.value {
+            Value::Flag => return true,
+            Value::Assign(expr) => Some(&**expr),
+            _ => None,
+        };
+
+        match expr {
+            Some(Expr::Lit(syn::ExprLit {
+                lit: Lit::Bool(b), ..
+            })) => b.value,
+            _ => {
+                emit_error!(
+                    self.span(),
+                    "Expected flag `{}` or `{} = true`",
+                    self.key,
+                    self.key
+                );
+                false
+            }
+        }
+    }
+
+    pub fn list(&self) -> Args {
+        match &self.value {
+            Value::List(group) if group.delimiter() == Delimiter::Parenthesis => {
+                syn::parse2(group.stream()).unwrap_or_else(|e| abort!(e.span(), "{}", e))
+            }
+            _ => abort!(self.span(), "Expected a list `{}(...)`", self.key),
+        }
+    }
+
+    /// A list holding a single expression, like `length(1..=64)`.
+    pub fn list_expr(&self) -> Expr {
+        match &self.value {
+            Value::List(group) if group.delimiter() == Delimiter::Parenthesis => {
+                syn::parse2(group.stream()).unwrap_or_else(|e| abort!(e.span(), "{}", e))
+            }
+            _ => abort!(self.span(), "Expected an expression `{}(...)`", self.key),
+        }
+    }
+
+    pub fn selection(&self) -> (String, Span) {
+        match &self.value {
+            Value::Name(name, span) => (name.clone(), *span),
+            _ => abort!(self.span(), "Expected a name `{} name`", self.key),
+        }
+    }
+
+    pub fn expr(&self) -> &Expr {
+        match &self.value {
+            Value::Assign(expr) => expr,
+            _ => abort!(self.span(), "Expected an assignment `{} = ...`", self.key),
+        }
+    }
+
+    pub fn ident(&self) -> Ident {
+        match self.expr() {
+            Expr::Path(p) if p.path.get_ident().is_some() => p.path.get_ident().unwrap().clone(),
+            e => abort!(e.span(), "Expected an identifier `{} = Name`", self.key),
+        }
+    }
+
+    pub fn path(&self) -> syn::Path {
+        match self.expr() {
+            Expr::Path(p) => p.path.clone(),
+            e => abort!(e.span(), "Expected a path `{} = path::to::Item`", self.key),
+        }
+    }
+
+    pub fn string(&self) -> String {
+        match self.expr() {
+            Expr::Lit(syn::ExprLit {
+                lit: Lit::Str(s), ..
+            }) => s.value(),
+            e => abort!(e.span(), "Expected a string `{} = \"...\"`", self.key),
+        }
+    }
+
+    pub fn int<N>(&self) -> N
+    where
+        N: std::str::FromStr,
+        N::Err: std::fmt::Display,
+    {
+        let (neg, lit) = match self.expr() {
+            Expr::Lit(syn::ExprLit {
+                lit: Lit::Int(i), ..
+            }) => (false, i),
+            Expr::Unary(syn::ExprUnary {
+                op: syn::UnOp::Neg(_),
+                expr,
+                ..
+            }) => match &**expr {
+                Expr::Lit(syn::ExprLit {
+                    lit: Lit::Int(i), ..
+                }) => (true, i),
+                e => abort!(e.span(), "Expected an integer `{} = 1`", self.key),
+            },
+            e => abort!(e.span(), "Expected an integer `{} = 1`", self.key),
+        };
+
+        let digits = format!("{}{}", if neg { "-" } else { "" }, lit.base10_digits());
+        digits
+            .parse()
+            .unwrap_or_else(|e| abort!(lit.span(), "Invalid value for {}: {}", self.key, e))
+    }
+
+    /// Reports the argument as not understood in the current position.
+    pub fn unknown(&self, context: &str, known: &[&str]) {
+        let name = self.name();
+
+        match suggest(&name, known) {
+            Some(s) => emit_error!(
+                self.key.span(), "Unknown {} `{}`", context, name;
+                help = "did you mean `{}`?", s
+            ),
+            None => emit_error!(
+                self.key.span(), "Unknown {} `{}`", context, name;
+                help = "expected one of {}", known.join(", ")
+            ),
+        }
+    }
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = (prev + (ca != *cb) as usize).min(row[j] + 1).min(cur + 1);
+            prev = cur;
+        }
+    }
+
+    row[b.len()]
+}
+
+pub fn suggest<'a>(name: &str, known: &[&'a str]) -> Option<&'a str> {
+    known
+        .iter()
+        .map(|k| (distance(name, k), *k))
+        .filter(|(d, k)| *d <= (k.len() / 3).max(2))
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k)
+}
+
+#[cfg(test)]
+mod tests {
+    use syn::Visibility;
+
+    use super::{distance, suggest, Args, Value};
+
+    #[test]
+    fn it_suggests_close_names() {
+        assert_eq!(distance("kitten", "sitting"), 3);
+        assert_eq!(distance("", "abc"), 3);
+        assert_eq!(distance("unique", "unique"), 0);
+
+        let known = ["unique", "sparse", "type", "name"];
+        assert_eq!(suggest("uniqe", &known), Some("unique"));
+        assert_eq!(suggest("tpye", &known), Some("type"));
+        assert_eq!(suggest("expire", &known), None);
+    }
+
+    #[test]
+    fn it_parses_arguments() {
+        let args: Args = syn::parse_str(
+            r#"pub UserColl users, single "e-mail", unique, type = Text, index(compound a, sparse)"#,
+        )
+        .unwrap();
+        let args = args.0;
+
+        assert_eq!(args.len(), 5);
+        assert!(matches!(args[0].vis, Visibility::Public(_)));
+        assert!(matches!(&args[0].value, Value::Name(n, _) if n == "users"));
+        assert_eq!(args[0].name(), "UserColl");
+        assert!(matches!(&args[1].value, Value::Name(n, _) if n == "e-mail"));
+        assert!(matches!(args[2].value, Value::Flag));
+        assert!(args[2].flag());
+        assert!(matches!(args[3].value, Value::Assign(_)));
+        assert_eq!(args[3].ident(), "Text");
+
+        let Value::List(_) = args[4].value else {
+            panic!("index is not a list");
+        };
+        let nested = args[4].list().0;
+        assert_eq!(nested[0].name(), "compound");
+        assert_eq!(nested[1].name(), "sparse");
+
+        let args: Args = syn::parse_str("length(1..=64), offset = -1").unwrap();
+        assert!(matches!(args.0[0].list_expr(), syn::Expr::Range(_)));
+        assert_eq!(args.0[1].int::<i64>(), -1);
+        assert!(syn::parse_str::<Args>("single email unique").is_err());
+    }
+}
@@ -0,0 +1,47 @@
+//! The typed field paths of a document, see `collection::filter`.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::Visibility;
+
+use crate::schema;
+
+/// `UserFields`, holding a `Field` for every serialized field of `User`, as `UserColl::FIELDS`.
+pub fn derive(
+    vis: &Visibility,
+    source_id: &syn::Ident,
+    coll_struct_id: &syn::Ident,
+    fields: &syn::Fields,
+    rename_all: Option<&str>,
+) -> TokenStream {
+    let fields_id = format_ident!("{}Fields", source_id);
+
+    let fields = fields
+        .iter()
+        .filter(|field| {
+            let attrs = schema::serde_attrs(&field.attrs);
+            field.ident.is_some() && !attrs.skip && !attrs.flatten
+        })
+        .collect::<Vec<_>>();
+    let idents = fields.iter().map(|field| field.ident.as_ref().unwrap());
+    let tys = fields.iter().map(|field| &field.ty);
+    let names = fields.iter().map(|field| {
+        schema::field_name(field, schema::serde_attrs(&field.attrs).rename, rename_all)
+    });
+    let idents2 = idents.clone();
+    let doc = format!("The field paths of [`{}`].", source_id);
+
+    quote! {
+        #[doc = #doc]
+        #vis struct #fields_id {
+            #(pub #idents: ::collection::filter::Field<#source_id, #tys>,)*
+        }
+
+        impl #coll_struct_id {
+            /// The typed field paths of the document, see `collection::filter`.
+            pub const FIELDS: #fields_id = #fields_id {
+                #(#idents2: ::collection::filter::Field::new(#names),)*
+            };
+        }
+    }
+}
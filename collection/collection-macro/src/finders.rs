@@ -0,0 +1,154 @@
+//! The `by_` finders generated for the declared indexes, see `collection::finders`.
+
+use std::collections::{HashMap, HashSet};
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+
+use crate::{schema, CompoundIndex, IndexType, SingleFieldIndex};
+
+pub struct Plan<'a> {
+    pub method: String,
+    pub index: &'a str,
+    pub fields: Vec<&'a syn::Field>,
+    pub unique: bool,
+    pub prefix: bool,
+}
+
+/// `by_email` for the index `email`, or `by_email` itself.
+fn method(name: &str) -> String {
+    if name.starts_with("by_") {
+        name.to_owned()
+    } else {
+        format!("by_{}", name)
+    }
+}
+
+/// Whether a key of this type can be matched by value.
+fn by_value(ty: &IndexType) -> bool {
+    matches!(ty, IndexType::Up | IndexType::Down | IndexType::Hash)
+}
+
+/// The type a finder takes the value of `field` as.
+pub fn arg_type(field: &syn::Field) -> TokenStream {
+    match &field.ty {
+        syn::Type::Path(p) if p.path.is_ident("String") => quote!(&str),
+        ty => quote!(&#ty),
+    }
+}
+
+pub fn plans<'a>(
+    single_indexes: &'a HashMap<String, SingleFieldIndex>,
+    compound_indexes: &'a HashMap<String, CompoundIndex>,
+) -> Vec<Plan<'a>> {
+    let mut singles = single_indexes.iter().collect::<Vec<_>>();
+    singles.sort_by_key(|(name, _)| *name);
+    let mut compounds = compound_indexes.iter().collect::<Vec<_>>();
+    compounds.sort_by_key(|(name, _)| *name);
+
+    let mut plans = Vec::new();
+    for (name, index) in singles.into_iter().filter(|(_, index)| by_value(&index.ty)) {
+        plans.push(Plan {
+            method: method(name),
+            index: name,
+            fields: vec![&index.field],
+            unique: index.index_info.unique,
+            prefix: false,
+        });
+    }
+
+    let mut prefixes = Vec::new();
+    for (name, index) in compounds
+        .into_iter()
+        .filter(|(_, index)| !index.fields.is_empty())
+    {
+        let usable = index
+            .fields
+            .iter()
+            .take_while(|(_, ty, _)| by_value(ty))
+            .map(|(field, _, _)| field)
+            .collect::<Vec<_>>();
+
+        if !usable.is_empty() && usable.len() == index.fields.len() {
+            plans.push(Plan {
+                method: method(name),
+                index: name,
+                fields: usable.clone(),
+                unique: index.index_info.unique,
+                prefix: false,
+            });
+        }
+
+        for len in 1..=usable.len().min(index.fields.len() - 1) {
+            let fields = usable[..len].to_vec();
+            let idents = fields
+                .iter()
+                .map(|field| field.ident.as_ref().unwrap().unraw().to_string())
+                .collect::<Vec<_>>();
+            prefixes.push(Plan {
+                method: format!("by_{}", idents.join("_")),
+                index: name,
+                fields,
+                unique: false,
+                prefix: true,
+            });
+        }
+    }
+
+    // Finders of whole indexes take precedence over prefixes of the same name.
+    let mut taken = HashSet::new();
+    plans
+        .into_iter()
+        .chain(prefixes)
+        .filter(|plan| taken.insert(plan.method.clone()))
+        .collect()
+}
+
+pub fn derive(
+    single_indexes: &HashMap<String, SingleFieldIndex>,
+    compound_indexes: &HashMap<String, CompoundIndex>,
+    rename_all: Option<&str>,
+) -> TokenStream {
+    let finders = plans(single_indexes, compound_indexes)
+        .into_iter()
+        .map(|plan| {
+            let method = format_ident!("{}", plan.method);
+            let args = plan
+                .fields
+                .iter()
+                .map(|field| field.ident.clone().unwrap())
+                .collect::<Vec<_>>();
+            let tys = plan.fields.iter().map(|field| arg_type(field));
+            let names = plan.fields.iter().map(|field| {
+                schema::field_name(field, schema::serde_attrs(&field.attrs).rename, rename_all)
+            });
+
+            let fetcher = if plan.unique {
+                quote!(::collection::fetchers::FindOne)
+            } else {
+                quote!(::collection::fetchers::FindStream)
+            };
+            let doc = if plan.prefix {
+                format!(
+                    "Finds by the first {} fields of the `{}` index.",
+                    plan.fields.len(),
+                    plan.index
+                )
+            } else {
+                format!("Finds by the `{}` index.", plan.index)
+            };
+
+            quote! {
+                #[doc = #doc]
+                pub fn #method(#(#args: #tys),*) -> ::collection::finders::Finder<#fetcher> {
+                    let filter = ::collection::finders::filter([
+                        #((#names, ::mongodb::bson::to_bson(#args))),*
+                    ]);
+                    ::collection::finders::Finder(filter.map(#fetcher))
+                }
+            }
+        });
+
+    quote! { #(#finders)* }
+}
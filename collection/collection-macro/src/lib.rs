@@ -1,22 +1,29 @@
-#![feature(iter_intersperse)]
 use std::collections::HashMap;
 
 use proc_macro::TokenStream as Ts1;
 use proc_macro2::{Ident, Span};
-use proc_macro2::{TokenStream, TokenTree};
-use proc_macro_error::{abort, proc_macro_error, OptionExt, ResultExt};
-use quote::quote;
+use proc_macro_error::{abort, emit_error, proc_macro_error, ResultExt};
 use quote::ToTokens;
+use quote::{format_ident, quote, quote_spanned};
 use syn::spanned::Spanned;
-use syn::{parse2, parse_macro_input, DeriveInput, Expr, ItemStruct};
+use syn::{parse2, parse_macro_input, DeriveInput};
 use syn::{Attribute, Visibility};
 
+mod active_record;
+mod attr;
+mod fields;
+mod finders;
+mod schema;
+mod validate;
+
+use attr::{Arg, Args, Value};
+
 #[derive(Debug)]
 enum Db {
     Mongo,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 enum IndexType {
     Up,
     Down,
@@ -45,12 +52,13 @@ struct IndexField {
     pub ty: IndexType,
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, PartialEq, Eq)]
 struct IndexInfo {
     pub expire_after_seconds: Option<u64>,
     pub unique: bool,
     pub sparse: bool,
     pub hidden: bool,
+    pub active_only: bool,
 }
 
 #[derive(Debug)]
@@ -58,319 +66,678 @@ struct SingleFieldIndex {
     pub field: syn::Field,
     pub index_info: IndexInfo,
     pub ty: IndexType,
+    pub span: Span,
 }
 #[derive(Debug)]
 struct CompoundIndex {
-    pub fields: Vec<(syn::Field, IndexType)>,
+    pub fields: Vec<(syn::Field, IndexType, Span)>,
     pub index_info: IndexInfo,
-}
-
-#[derive(Debug)]
-enum TargetAttr {
-    Coll(TokenStream),
-    CollOption(String, String, Span),
-    CollIndex(TokenStream),
-
-    SerdeRename(String),
-    SerdeRenameAll(String),
+    pub span: Span,
 }
 
 #[derive(Default)]
 struct Options {
     pub collection_sharing: bool,
+    pub version: Option<u32>,
+    pub id: Option<proc_macro2::TokenStream>,
+    /// The type of the `#[coll(id)]` field.
+    pub id_field: Option<syn::Type>,
+    /// The `#[coll(id = Sequence)]` argument, if the id is numbered from a sequence.
+    pub id_sequence: Option<Span>,
+    /// The `#[coll(sequence = ...)]` argument of the id field.
+    pub sequence_id: Option<Span>,
+    /// Serialized field name and sequence name of every `#[coll(sequence = ...)]`.
+    pub sequences: Vec<(String, String)>,
+    /// Serialized name of the `#[coll(version)]` field.
+    pub version_field: Option<String>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub server_timestamps: bool,
+    /// The `deleted_at` field of soft deletes, by its Rust name until the fields are parsed.
+    pub soft_delete: Option<String>,
+    pub hooks: bool,
+    /// Whether the `validate` rules are added to the `$jsonSchema`.
+    pub mirror_validation: bool,
+    pub history: bool,
+    pub active_record: bool,
 }
 
-fn match_equality(gr: TokenStream, allow_implicit: bool) -> Option<(String, String)> {
-    let mut it = gr.into_iter();
-    match (it.next(), it.next(), it.next(), it.next()) {
-        (
-            Some(TokenTree::Ident(left)),
-            Some(TokenTree::Punct(p)),
-            Some(TokenTree::Ident(right)),
-            None,
-        ) if p.as_char() == '=' => Some((left.to_string(), right.to_string())),
-
-        (Some(TokenTree::Ident(left)), None, None, None) if allow_implicit => {
-            Some((left.to_string(), "true".to_string()))
-        }
-        _ => None,
-    }
+struct Header {
+    pub vis: Visibility,
+    pub ident: Ident,
+    pub name: String,
 }
 
-impl TargetAttr {
-    fn build_option(gr: TokenStream, loc: Span) -> TargetAttr {
-        let (left, right) = match_equality(gr, true)
-            .unwrap_or_else(|| abort!(loc, "Expected equality: #[coll(attr = value)]"));
-        Self::CollOption(left, right, loc)
-    }
-    fn transform_into_expected_values(self) -> Self {
-        use TargetAttr::*;
+struct IndexAttr {
+    pub name: String,
+    pub is_single: bool,
+    pub info: IndexInfo,
+    pub ty: IndexType,
+    pub span: Span,
+}
 
-        match self {
-            Coll(a) => {
-                let loc = a.span();
+const STRUCT_KEYS: &[&str] = &["index", "option", "version", "hooks"];
+const FIELD_KEYS: &[&str] = &[
+    "index",
+    "id",
+    "sequence",
+    "version",
+    "created_at",
+    "updated_at",
+    "validate",
+];
+const OPTION_KEYS: &[&str] = &[
+    "collection_sharing",
+    "server_timestamps",
+    "soft_delete",
+    "mirror_validation",
+    "history",
+    "active_record",
+];
+const INDEX_KEYS: &[&str] = &[
+    "single",
+    "compound",
+    "unique",
+    "sparse",
+    "hidden",
+    "expire_after_seconds",
+    "active_only",
+    "type",
+];
+const INDEX_TYPES: &[&str] = &[
+    "Up",
+    "Down",
+    "Text",
+    "Geo2D",
+    "Geo2DSphere",
+    "GeoHaystack",
+    "Hash",
+];
 
-                let mut it = a.clone().into_iter();
+fn parse_index_type(arg: &Arg) -> IndexType {
+    let id = arg.ident();
 
-                let (Some(TokenTree::Ident(id)),Some(TokenTree::Group(gr)),None) = (it.next(), it.next(), it.next()) else {
-                     return Coll(a)
-                };
-                match id.to_string().as_str() {
-                    "option" => Self::build_option(gr.stream(), loc),
-                    "index" => CollIndex(gr.stream()),
-                    _ => abort!(loc, "Unexpected function call"),
-                }
+    match id.to_string().as_str() {
+        "Up" => IndexType::Up,
+        "Down" => IndexType::Down,
+        "Text" => IndexType::Text,
+        "Geo2D" => IndexType::Geo2D,
+        "Geo2DSphere" => IndexType::Geo2DSphere,
+        "GeoHaystack" => IndexType::GeoHaystack,
+        "Hash" => IndexType::Hash,
+        other => {
+            match attr::suggest(other, INDEX_TYPES) {
+                Some(s) => emit_error!(
+                    id.span(), "Unknown index type `{}`", other;
+                    help = "did you mean `{}`?", s
+                ),
+                None => emit_error!(
+                    id.span(), "Unknown index type `{}`", other;
+                    help = "expected one of {}", INDEX_TYPES.join(", ")
+                ),
             }
-            a => a,
+            IndexType::Up
         }
     }
-
-    fn filter_map(attr: &Attribute) -> Option<Self> {
-        attr.path
-            .get_ident()
-            .map(|v| v.to_string())
-            .and_then(|v| match v.as_ref() {
-                "coll" => Some(match get_inner_tokens(attr.clone().tokens) {
-                    Some(v) => Self::Coll(v),
-                    None => abort!(attr.span(), "coll macro requires arguments #[coll(...)]"),
-                }),
-                "coll" => Some(match get_inner_tokens(attr.clone().tokens) {
-                    Some(v) => Self::Coll(v),
-                    None => abort!(attr.span(), "coll macro requires arguments #[coll(...)]"),
-                }),
-                _ => None,
-            })
-    }
 }
 
-fn get_inner_tokens(tokens: TokenStream) -> Option<TokenStream> {
-    let mut iter = tokens.into_iter();
-
-    if let TokenTree::Group(inner) = iter.next()? {
-        Some(inner.stream())
-    } else {
-        None
-    }
-}
-
-fn parse_index_data(
-    mut inner: impl Iterator<Item = TokenTree>,
-    loc: Span,
-    incl_type_parsing: bool,
-    incl_info_parsing: bool,
-) -> (IndexInfo, IndexType) {
+/// Parses `index(single name, ...)` or `index(compound name, ...)`. Arguments may come in any
+/// order. Index options are only accepted where the whole index is declared, while the type of a
+/// compound index is given per field.
+fn parse_index_attr(arg: &Arg, on_field: bool) -> IndexAttr {
+    let mut selection = None;
     let mut info = IndexInfo::default();
     let mut ty = IndexType::Up;
-    loop {
-        match (inner.next(), inner.next()) {
-            (Some(TokenTree::Ident(v)), Some(TokenTree::Punct(p)))
-                if v.to_string().as_str() == "sparse" && p.as_char() == ',' && incl_info_parsing=>
-            {
-                info.sparse = true;
-            }
-            (Some(TokenTree::Ident(v)), Some(TokenTree::Punct(p)))
-                if v.to_string().as_str() == "unique" && p.as_char() == ',' && incl_info_parsing=>
-            {
-                info.unique = true;
-            }
-            (Some(TokenTree::Ident(v)), None)
-                if v.to_string().as_str() == "sparse" && incl_info_parsing=>
-            {
-                info.sparse = true;
-                break;
-            }
-            (Some(TokenTree::Ident(v)), None)
-                if v.to_string().as_str() == "unique" && incl_info_parsing=>
-            {
-                info.unique = true;
-                break;
-            }
-            (Some(TokenTree::Ident(v)), Some(TokenTree::Punct(p)))
-                if v.to_string().as_str() == "type" && p.as_char() == '=' && incl_type_parsing && incl_info_parsing=>
-            {
-                match match (inner.next(), inner.next()) {
-                    (Some(TokenTree::Ident(id)), Some(TokenTree::Punct(p))) if p.as_char() == ','=> id,
-                    (Some(TokenTree::Ident(id)), None) => id,
-                    _ => abort!(loc, "Please follow type assignment by id and then termination or continuation (, ...)")
-                } .to_string().as_str(){
-                    "Up" => ty = IndexType::Up,
-                    "Down" => ty = IndexType::Down,
-                    "Text"=> ty = IndexType::Text,
-                    "Geo2D"=> ty = IndexType::Geo2D,
-                    "Geo2DSphere"=> ty = IndexType::Geo2DSphere,
-                    "GeoHaystack"=> ty = IndexType::GeoHaystack,
-                    "Hash"=> ty = IndexType::Hash,
-                    _ => abort!(loc, "Please follow type assignment by one of Up, Down, Text, Geo2D, Geo2DSphere, GeoHaystack or Hash"),
+
+    for inner in arg.list().0 {
+        inner.no_vis();
+
+        match inner.name().as_str() {
+            kind @ ("single" | "compound") => {
+                if selection.is_some() {
+                    emit_error!(inner.span(), "Index selected more than once");
                 }
+                let (name, _) = inner.selection();
+                selection = Some((name, kind == "single"));
             }
-            (None, _) => break,
-            _ => abort!(loc, "Expected assignment or value #[coll(index single name, type=Text, sparse)]"),
+            "type" => ty = parse_index_type(&inner),
+            "unique" => info.unique = inner.flag(),
+            "sparse" => info.sparse = inner.flag(),
+            "hidden" => info.hidden = inner.flag(),
+            "expire_after_seconds" => info.expire_after_seconds = Some(inner.int()),
+            "active_only" => info.active_only = inner.flag(),
+            _ => inner.unknown("index argument", INDEX_KEYS),
         }
     }
 
-    (info, ty)
-}
-
-fn parse_index_attr(
-    g: TokenStream,
-    loc: Span,
-    allow_compound: bool,
-    allow_single: bool,
-) -> (Ident, bool, IndexInfo, IndexType) {
-    let mut inner = g.clone().into_iter();
-
-    let (index, is_single) = match (inner.next(), inner.next(), inner.next()) {
-            (
-                Some(TokenTree::Ident(v)),
-                Some(TokenTree::Ident(id)),
-                Some(TokenTree::Punct(p)),
-                ) if v.to_string().as_str() == "single" && p.as_char() == ',' && allow_single=> (id, true),
-                (
-                    Some(TokenTree::Ident(v)),
-                    Some(TokenTree::Ident(id)),
-                    Some(TokenTree::Punct(p)),
-                    ) if v.to_string().as_str() == "compound" && p.as_char() == ',' && allow_compound=> {
-                    (id, false)
-                }
-            (
-                Some(TokenTree::Ident(v)),
-                Some(TokenTree::Ident(id)),
-                None,
-                ) if v.to_string().as_str() == "single" && allow_single => (id, true),
-            (
-                Some(TokenTree::Ident(v)),
-                Some(TokenTree::Ident(id)),
-                None,
-                ) if v.to_string().as_str() == "compound" && allow_compound => {
-                (id, false)
-            }
-            _ if !allow_single => abort!(loc, "Please provide index selection on form #[coll(index(compound name, ...))]"),
-            _ if !allow_compound => abort!(loc, "Please provide index selection on form #[coll(index(single name, ...))]"),
-            _ => abort!(loc, "Please provide index selection on form #[coll(index(single name, ...))] or #[coll(index(compound name, ...))]"),
+    let Some((name, is_single)) = selection else {
+        abort!(
+            arg.span(),
+            "Please provide index selection on form #[coll(index(single name, ...))] or #[coll(index(compound name, ...))]"
+        );
     };
 
-    let (index_info, ty) = parse_index_data(inner, g.span(), is_single, true);
+    if !on_field && is_single {
+        emit_error!(
+            arg.span(),
+            "Single field indexes are declared on their field"
+        );
+    }
+    if !on_field && ty != IndexType::Up {
+        emit_error!(
+            arg.span(),
+            "The type of a compound index is declared on each of its fields"
+        );
+    }
+    if on_field && !is_single && info != IndexInfo::default() {
+        emit_error!(
+            arg.span(),
+            "Options of compound index {} must be declared on the struct #[coll(index(compound {}, ...))]",
+            name,
+            name
+        );
+    }
 
-    (index, is_single, index_info, ty)
+    IndexAttr {
+        name,
+        is_single,
+        info,
+        ty,
+        span: arg.span(),
+    }
 }
 
-fn get_db_info<'a>(attrs: &mut impl Iterator<Item = &'a syn::Attribute>) {}
-
 type CompoundIndexes = HashMap<String, CompoundIndex>;
 
-fn parse_primary_attrs(
-    mut attrs: impl Iterator<Item = TargetAttr>,
-    options: &mut Options,
-) -> ((Visibility, Ident, Ident), CompoundIndexes) {
+fn parse_primary_attrs(attrs: &[Attribute], options: &mut Options) -> (Header, CompoundIndexes) {
     let mut compounds = HashMap::new();
+    let mut header = None;
 
-    let Some(TargetAttr::Coll(t)) = attrs.next() else {
-        abort!(Span::call_site(),"Database specifier must come before any. Example #[coll(pub UserCollection : \"users\")]");
-    };
-
-    let loc = t.span();
-    let mut t = t.into_iter();
-
-    let v = match (
-        t.next(),
-        // (t.next().and_then(|v| parse2::<syn::Expr>(v.into()).ok())),
-        t.next(),
-        t.next(),
-        t.next(),
-    ) {
-        (Some(TokenTree::Ident(id)), Some(TokenTree::Ident(v)), None, None) => {
-            (syn::Visibility::Inherited, id, v)
-        }
-        (Some(vis), Some(TokenTree::Ident(id)), Some(TokenTree::Ident(v)), None) => {
-            let Ok(vis) = parse2::<syn::Visibility> (vis.to_token_stream()) else {abort!(loc,"Expected visability #[coll(pub UserCollection users)]");};
-
-            (vis, id, v)
-        }
-
-        _ => abort!(
-            loc,
-            "Expected the first coll attr to be #[coll(pub UserCollection users)]"
-        ),
-    };
-
-    for attr in attrs {
-        match attr {
-            TargetAttr::CollIndex(v) => {
-                let loc = v.span();
-                let (index, _, index_info, _) = parse_index_attr(v, loc, true, false);
+    for arg in Args::from_attrs(attrs).0 {
+        match (&arg.value, arg.name().as_str()) {
+            (_, "index") => {
+                arg.no_vis();
+                let index = parse_index_attr(&arg, false);
 
-                compounds.insert(
-                    index.to_string(),
+                let prev = compounds.insert(
+                    index.name.clone(),
                     CompoundIndex {
                         fields: Vec::new(),
-                        index_info,
+                        index_info: index.info,
+                        span: index.span,
                     },
                 );
+                if prev.is_some() {
+                    emit_error!(
+                        index.span,
+                        "Compound index {} declared more than once",
+                        index.name
+                    );
+                }
             }
-
-            TargetAttr::Coll(a) => abort!(
-                a.span(),
-                "Unexpected tokens {}, expected index or option specification",
-                a
-            ),
-
-            TargetAttr::CollOption(left, right, loc) => match left.as_str() {
-                "collection_sharing" => {
-                    options.collection_sharing = right.parse().unwrap_or_else(move |_| {
-                        abort!(loc, "Option collection_sharing must be a bool")
-                    })
+            (_, "option") => {
+                arg.no_vis();
+                for opt in arg.list().0 {
+                    match opt.name().as_str() {
+                        "collection_sharing" => options.collection_sharing = opt.flag(),
+                        "server_timestamps" => options.server_timestamps = opt.flag(),
+                        "soft_delete" => options.soft_delete = Some(opt.ident().to_string()),
+                        "mirror_validation" => options.mirror_validation = opt.flag(),
+                        "history" => options.history = opt.flag(),
+                        "active_record" => options.active_record = opt.flag(),
+                        _ => opt.unknown("option", OPTION_KEYS),
+                    }
+                }
+            }
+            (_, "version") => {
+                arg.no_vis();
+                if options.version.replace(arg.int()).is_some() {
+                    emit_error!(arg.span(), "Version declared more than once");
+                }
+            }
+            (_, "hooks") => {
+                arg.no_vis();
+                options.hooks = arg.flag();
+            }
+            (Value::Name(name, _), _) => {
+                if header.is_some() {
+                    emit_error!(arg.span(), "Collection declared more than once");
                 }
-                a => abort!(loc, "No option {}", a),
-            },
-            a => todo!("lol {:#?}", a),
+                header = Some(Header {
+                    vis: arg.vis.clone(),
+                    ident: arg.key.clone(),
+                    name: name.clone(),
+                });
+            }
+            _ => arg.unknown("coll argument", STRUCT_KEYS),
         }
     }
 
-    (v, compounds)
+    let Some(header) = header else {
+        abort!(
+            Span::call_site(),
+            "Expected the collection to be declared as #[coll(pub UserCollection users)]"
+        );
+    };
+
+    (header, compounds)
+}
+
+/// The strategy type of `#[coll(id)]` or `#[coll(id = ...)]`.
+fn parse_id_strategy(arg: &Arg) -> proc_macro2::TokenStream {
+    if let Value::Flag = arg.value {
+        return quote!(::collection::id::ObjectIdStrategy);
+    }
+
+    let path = arg.path();
+    match path.get_ident().map(ToString::to_string).as_deref() {
+        Some("ObjectId") => quote!(::collection::id::ObjectIdStrategy),
+        Some("UuidV4") => quote!(::collection::id::UuidV4Strategy),
+        Some("UuidV7") => quote!(::collection::id::UuidV7Strategy),
+        Some("String") => quote!(::collection::id::StringStrategy),
+        Some("Sequence") => quote!(::collection::id::SequenceStrategy),
+        _ => path.to_token_stream(),
+    }
 }
 
 fn handle_struct_body(
     item: syn::ItemStruct,
     compounds: &mut CompoundIndexes,
+    options: &mut Options,
+    rename_all: Option<&str>,
 ) -> HashMap<String, SingleFieldIndex> {
     let mut single_fields = HashMap::new();
 
     for field in &item.fields {
-        let cfield = field.clone();
-
-        for attr in field.attrs.iter().filter_map(TargetAttr::filter_map) {
-            match attr {
-                TargetAttr::CollIndex(index) => {
-                    let loc = index.span();
-                    let (index, is_single, index_info, ty) =
-                        parse_index_attr(index, loc, true, true);
-
-                    let index = index.to_string();
-                    if is_single {
-                        single_fields.insert(
-                            index,
+        for arg in Args::from_attrs(&field.attrs).0 {
+            arg.no_vis();
+
+            match arg.name().as_str() {
+                "index" => {
+                    let index = parse_index_attr(&arg, true);
+
+                    if index.is_single {
+                        let prev = single_fields.insert(
+                            index.name.clone(),
                             SingleFieldIndex {
-                                field: cfield.clone(),
-                                index_info,
-                                ty,
+                                field: field.clone(),
+                                index_info: index.info,
+                                ty: index.ty,
+                                span: index.span,
                             },
                         );
+                        if prev.is_some() {
+                            emit_error!(index.span, "Index {} declared more than once", index.name);
+                        }
+                    } else if let Some(compound) = compounds.get_mut(&index.name) {
+                        compound.fields.push((field.clone(), index.ty, index.span));
+                    } else {
+                        let known = compounds.keys().map(String::as_str).collect::<Vec<_>>();
+                        match attr::suggest(&index.name, &known) {
+                            Some(s) => emit_error!(
+                                index.span, "No compound index with name {}", index.name;
+                                help = "did you mean `{}`?", s
+                            ),
+                            None => emit_error!(
+                                index.span, "No compound index with name {}", index.name;
+                                help = "declare it on the struct with #[coll(index(compound {}))]", index.name
+                            ),
+                        }
+                    }
+                }
+                "id" => {
+                    let name = schema::field_name(
+                        field,
+                        schema::serde_attrs(&field.attrs).rename,
+                        rename_all,
+                    );
+                    if name != "_id" {
+                        emit_error!(
+                            arg.span(), "The id field is serialized as `{}` instead of `_id`", name;
+                            help = "add #[serde(rename = \"_id\")] to the field"
+                        );
+                    }
+                    if options.id.replace(parse_id_strategy(&arg)).is_some() {
+                        emit_error!(arg.span(), "Id declared more than once");
+                    }
+                    options.id_field = Some(field.ty.clone());
+                    if !matches!(arg.value, Value::Flag) && arg.path().is_ident("Sequence") {
+                        options.id_sequence = Some(arg.span());
+                    }
+                }
+                "sequence" => {
+                    let name = schema::field_name(
+                        field,
+                        schema::serde_attrs(&field.attrs).rename,
+                        rename_all,
+                    );
+                    if name == "_id" {
+                        options.sequence_id = Some(arg.span());
+                    }
+                    if options.sequences.iter().any(|(f, _)| *f == name) {
+                        emit_error!(arg.span(), "Sequence of {} declared more than once", name);
+                    }
+                    options.sequences.push((name, arg.string()));
+                }
+                "version" => {
+                    if !arg.flag() {
+                        continue;
+                    }
+                    let name = schema::field_name(
+                        field,
+                        schema::serde_attrs(&field.attrs).rename,
+                        rename_all,
+                    );
+                    if options.version_field.replace(name).is_some() {
+                        emit_error!(arg.span(), "Version field declared more than once");
+                    }
+                }
+                kind @ ("created_at" | "updated_at") => {
+                    if !arg.flag() {
+                        continue;
+                    }
+                    let name = schema::field_name(
+                        field,
+                        schema::serde_attrs(&field.attrs).rename,
+                        rename_all,
+                    );
+                    let slot = if kind == "created_at" {
+                        &mut options.created_at
                     } else {
-                        compounds
-                            .get_mut(&index)
-                            .expect_or_abort(format!("No index with name {}", index).as_str())
-                            .fields
-                            .push((cfield.clone(), ty));
+                        &mut options.updated_at
+                    };
+                    if slot.replace(name).is_some() {
+                        emit_error!(arg.span(), "Field {} declared more than once", kind);
                     }
                 }
-                _ => todo!(),
+                // parsed by validate::parse
+                "validate" => {}
+                _ => arg.unknown("field argument", FIELD_KEYS),
             }
         }
     }
 
+    match (options.id_sequence, options.sequence_id) {
+        (Some(span), None) => emit_error!(
+            span, "The id is numbered from a sequence but none is named";
+            help = "add #[coll(sequence = \"...\")] to the id field"
+        ),
+        (None, Some(span)) => emit_error!(
+            span, "The id can only be numbered from a sequence with the Sequence strategy";
+            help = "declare the id with #[coll(id = Sequence)]"
+        ),
+        _ => {}
+    }
+
+    // The deleted_at field needn't be part of the document, but is renamed like one if it is.
+    if let Some(name) = &mut options.soft_delete {
+        let field = item
+            .fields
+            .iter()
+            .find(|field| field.ident.as_ref().is_some_and(|ident| ident == name));
+        if let Some(field) = field {
+            *name = schema::field_name(field, schema::serde_attrs(&field.attrs).rename, rename_all);
+        }
+    }
+
     single_fields
 }
 
+fn is_string(ty: &syn::Type) -> bool {
+    let syn::Type::Path(path) = ty else {
+        return false;
+    };
+    let Some(last) = path.path.segments.last() else {
+        return false;
+    };
+
+    match last.ident.to_string().as_str() {
+        "String" | "str" => true,
+        "Option" | "Box" => match &last.arguments {
+            syn::PathArguments::AngleBracketed(args) => args
+                .args
+                .iter()
+                .any(|arg| matches!(arg, syn::GenericArgument::Type(inner) if is_string(inner))),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn check_index_field(field: &syn::Field, ty: &IndexType, loc: Span) {
+    match ty {
+        IndexType::GeoHaystack => emit_error!(
+            loc,
+            "GeoHaystack indexes were removed in MongoDB 5.0, use Geo2DSphere instead"
+        ),
+        IndexType::Geo2D | IndexType::Geo2DSphere if is_string(&field.ty) => emit_error!(
+            loc,
+            "{:?} indexes require GeoJSON or coordinate pairs, but {} is a string",
+            ty,
+            field
+                .ident
+                .as_ref()
+                .map_or("the field".to_string(), ToString::to_string)
+        ),
+        _ => {}
+    }
+}
+
+/// Reports index declarations MongoDB would reject when the indexes are created. Warnings are
+/// returned as uses of deprecated items, since proc macros can't emit them on stable.
+fn validate_indexes(
+    singles: &HashMap<String, SingleFieldIndex>,
+    compounds: &CompoundIndexes,
+) -> proc_macro2::TokenStream {
+    let mut text_indexes = Vec::new();
+    let mut warnings = Vec::new();
+
+    for (name, index) in singles {
+        check_index_field(&index.field, &index.ty, index.span);
+
+        if index.ty == IndexType::Hash && index.index_info.unique {
+            emit_error!(index.span, "Hash indexes can not be unique");
+        }
+        if index.ty == IndexType::Text {
+            text_indexes.push((name, index.span));
+        }
+    }
+
+    for (name, index) in compounds {
+        if index.fields.is_empty() {
+            let note = format!(
+                "compound index {} is not joined by any field, add #[coll(index(compound {}))] to its fields",
+                name, name
+            );
+            warnings.push(quote_spanned! {index.span=>
+                const _: () = {
+                    #[deprecated(note = #note)]
+                    struct EmptyCompoundIndex;
+                    let _ = EmptyCompoundIndex;
+                };
+            });
+        }
+
+        for (field, ty, loc) in index.fields.iter() {
+            check_index_field(field, ty, *loc);
+        }
+
+        let has = |ty| index.fields.iter().any(|(_, t, _)| *t == ty);
+        if has(IndexType::Hash) && index.index_info.unique {
+            emit_error!(
+                index.span,
+                "Compound index {} contains a Hash field and can not be unique",
+                name
+            );
+        }
+        if has(IndexType::Text) {
+            text_indexes.push((name, index.span));
+        }
+    }
+
+    if text_indexes.len() > 1 {
+        text_indexes.sort_by(|a, b| a.0.cmp(b.0));
+        let names = text_indexes
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        for (_, loc) in text_indexes.iter() {
+            emit_error!(
+                *loc,
+                "A collection can only have one text index, found {}",
+                names
+            );
+        }
+    }
+
+    quote! { #(#warnings)* }
+}
+
+impl IndexType {
+    fn key(&self) -> proc_macro2::TokenStream {
+        match self {
+            IndexType::Up => quote!(1i32),
+            IndexType::Down => quote!(-1i32),
+            IndexType::Text => quote!("text"),
+            IndexType::Geo2D => quote!("2d"),
+            IndexType::Geo2DSphere => quote!("2dsphere"),
+            IndexType::GeoHaystack => quote!("geoHaystack"),
+            IndexType::Hash => quote!("hashed"),
+        }
+    }
+
+    /// How MongoDB spells the key in the names it derives for indexes.
+    fn suffix(&self) -> &'static str {
+        match self {
+            IndexType::Up => "1",
+            IndexType::Down => "-1",
+            IndexType::Text => "text",
+            IndexType::Geo2D => "2d",
+            IndexType::Geo2DSphere => "2dsphere",
+            IndexType::GeoHaystack => "geoHaystack",
+            IndexType::Hash => "hashed",
+        }
+    }
+}
+
+/// Generates the enum of declared indexes, e.g. `UserIndex`, and its `Index` impl.
+fn index_enum(
+    vis: &Visibility,
+    ident: &Ident,
+    singles: &HashMap<String, SingleFieldIndex>,
+    compounds: &CompoundIndexes,
+    rename_all: Option<&str>,
+    soft_delete: Option<&str>,
+) -> proc_macro2::TokenStream {
+    let mut indexes = singles
+        .iter()
+        .map(|(name, index)| {
+            let key = vec![(&index.field, &index.ty)];
+            (name, key, &index.index_info, index.span)
+        })
+        .chain(
+            compounds
+                .iter()
+                .filter(|(_, c)| !c.fields.is_empty())
+                .map(|(name, index)| {
+                    let keys = index.fields.iter().map(|(f, ty, _)| (f, ty)).collect();
+                    (name, keys, &index.index_info, index.span)
+                }),
+        )
+        .collect::<Vec<_>>();
+    indexes.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut variants = Vec::new();
+    let mut names = Vec::new();
+    let mut patterns = Vec::new();
+    let mut models = Vec::new();
+
+    for (name, keys, info, span) in indexes {
+        let variant = Ident::new(&schema::rename("PascalCase", name, false, span), span);
+
+        let mut inserts = Vec::new();
+        let mut default_name = Vec::new();
+        for (field, ty) in keys {
+            let field =
+                schema::field_name(field, schema::serde_attrs(&field.attrs).rename, rename_all);
+            let key = ty.key();
+
+            default_name.push(format!("{}_{}", field, ty.suffix()));
+            inserts.push(quote!(keys.insert(#field, #key);));
+        }
+
+        let IndexInfo {
+            expire_after_seconds,
+            unique,
+            sparse,
+            hidden,
+            active_only,
+        } = info;
+        let expire = match expire_after_seconds {
+            Some(secs) => quote!(Some(#secs)),
+            None => quote!(None),
+        };
+        let partial = match (active_only, soft_delete) {
+            (true, Some(field)) => quote!(Some(::collection::soft_delete::active_filter(#field))),
+            (true, None) => {
+                emit_error!(
+                    span,
+                    "Index {} is active_only, but the document has no soft delete", name;
+                    help = "enable it with #[coll(option(soft_delete = deleted_at))]"
+                );
+                quote!(None)
+            }
+            (false, _) => quote!(None),
+        };
+
+        variants.push(variant.clone());
+        names.push(name.clone());
+        let derived = default_name.join("_");
+        patterns.push(if derived == *name {
+            quote!(#name)
+        } else {
+            quote!(#name | #derived)
+        });
+        models.push(quote! {{
+            let mut keys = ::mongodb::bson::Document::new();
+            #(#inserts)*
+            ::collection::index::model(#name, keys, #unique, #sparse, #hidden, #expire, #partial)
+        }});
+    }
+
+    quote! {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #vis enum #ident {
+            #(#variants,)*
+        }
+
+        impl ::collection::index::Index for #ident {
+            const ALL: &'static [Self] = &[#(Self::#variants,)*];
+
+            fn name(self) -> &'static str {
+                match self {
+                    #(Self::#variants => #names,)*
+                }
+            }
+
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    #(#patterns => Some(Self::#variants),)*
+                    _ => None,
+                }
+            }
+
+            fn model(self) -> ::mongodb::IndexModel {
+                match self {
+                    #(Self::#variants => #models,)*
+                }
+            }
+        }
+    }
+}
+
 #[proc_macro_error]
 #[proc_macro_derive(Document, attributes(coll))]
 pub fn document(input: Ts1) -> Ts1 {
@@ -384,36 +751,274 @@ pub fn document(input: Ts1) -> Ts1 {
         )
     }
 
-    let ((vis, coll_struct_id, db_coll), mut compound_indexes) = parse_primary_attrs(
-        item.attrs
-            .iter()
-            .filter_map(TargetAttr::filter_map)
-            .map(TargetAttr::transform_into_expected_values),
-        &mut options,
-    );
+    let (header, mut compound_indexes) = parse_primary_attrs(&item.attrs, &mut options);
+    let Header {
+        vis,
+        ident: coll_struct_id,
+        name: db_coll,
+    } = header;
     let source_id = item.ident.clone();
+    let index_id = format_ident!("{}Index", source_id);
+    let rules = validate::parse(&item);
+    let validation = validate::derive(&item, &rules);
+    let json_schema = if options.mirror_validation {
+        schema::derive(&item, &validate::schema(&rules))
+    } else {
+        schema::derive(&item, &HashMap::new())
+    };
+    let rename_all = schema::serde_attrs(&item.attrs).rename_all;
+    let syn::Data::Struct(data) = &item.data else {
+        unreachable!()
+    };
+    let fields = fields::derive(
+        &vis,
+        &source_id,
+        &coll_struct_id,
+        &data.fields,
+        rename_all.as_deref(),
+    );
 
     let item = parse2::<syn::ItemStruct>(item.to_token_stream()).unwrap_or_abort();
-    let single_indexes = handle_struct_body(item, &mut compound_indexes);
+    let single_indexes = handle_struct_body(
+        item,
+        &mut compound_indexes,
+        &mut options,
+        rename_all.as_deref(),
+    );
+    let index_warnings = validate_indexes(&single_indexes, &compound_indexes);
+    let indexes = index_enum(
+        &vis,
+        &index_id,
+        &single_indexes,
+        &compound_indexes,
+        rename_all.as_deref(),
+        options.soft_delete.as_deref(),
+    );
+
+    let id_strategy = options
+        .id
+        .take()
+        .unwrap_or_else(|| quote!(::collection::id::ObjectIdStrategy));
+    let id_field = options.id_field.as_ref().map(|ty| {
+        quote_spanned! {ty.span()=>
+            const _: fn() = ::collection::id::assert_id_field::<#source_id, #ty>;
+        }
+    });
+    let versioning = options.version.map(|v| {
+        quote! {
+            const VERSION: u32 = #v;
+
+            fn migrations() -> &'static ::collection::migrate::Migrations {
+                static MIGRATIONS: ::std::sync::OnceLock<::collection::migrate::Migrations> =
+                    ::std::sync::OnceLock::new();
+                MIGRATIONS.get_or_init(<Self as ::collection::migrate::Migrate>::migrations)
+            }
+        }
+    });
+    let sequences = (!options.sequences.is_empty()).then(|| {
+        let (fields, names): (Vec<_>, Vec<_>) = options.sequences.iter().cloned().unzip();
+        quote! {
+            const SEQUENCES: &'static [(&'static str, &'static str)] = &[#((#fields, #names),)*];
+        }
+    });
+
+    let version_field = options.version_field.as_ref().map(|field| {
+        quote! {
+            const VERSION_FIELD: Option<&'static str> = Some(#field);
+        }
+    });
+
+    let timestamps = (options.created_at.is_some() || options.updated_at.is_some()).then(|| {
+        let field = |name: &Option<String>| match name {
+            Some(name) => quote!(Some(#name)),
+            None => quote!(None),
+        };
+        let created_at = field(&options.created_at);
+        let updated_at = field(&options.updated_at);
+        let server = options.server_timestamps;
+
+        quote! {
+            const TIMESTAMPS: ::collection::timestamps::Timestamps =
+                ::collection::timestamps::Timestamps {
+                    created_at: #created_at,
+                    updated_at: #updated_at,
+                    server: #server,
+                };
+        }
+    });
+
+    let soft_delete = options.soft_delete.as_ref().map(|field| {
+        quote! {
+            const SOFT_DELETE: Option<&'static str> = Some(#field);
+        }
+    });
+
+    let hooks = options.hooks.then(|| {
+        quote! {
+            const HOOKS: bool = true;
+
+            fn run_hook(
+                doc: &mut Self,
+                hook: ::collection::hooks::Hook,
+            ) -> impl ::std::future::Future<
+                Output = Result<(), ::collection::hooks::HookError>,
+            > + Send {
+                ::collection::hooks::run(doc, hook)
+            }
+        }
+    });
+
+    let finders = finders::derive(&single_indexes, &compound_indexes, rename_all.as_deref());
+    let active_record = options.active_record.then(|| {
+        active_record::derive(
+            &source_id,
+            &coll_struct_id,
+            &single_indexes,
+            &compound_indexes,
+            rename_all.as_deref(),
+        )
+    });
+
+    let bulk = (!options.history).then(|| {
+        quote! {
+            /// Starts a bulk write, see [`Bulk`](::collection::bulk::Bulk).
+            pub fn bulk(&self) -> ::collection::bulk::Bulk<'_, Self> {
+                ::collection::bulk::Bulk::new(self)
+            }
+        }
+    });
+
+    let history = options.history.then(|| {
+        quote! {
+            const HISTORY: bool = true;
+        }
+    });
 
     quote! {
+        #json_schema
+        #validation
+        #indexes
+        #index_warnings
+        #id_field
+        #fields
+        #active_record
+
         impl ::collection::Document for #source_id {
             type Collection = #coll_struct_id;
+            type Id = #id_strategy;
+            type Index = #index_id;
+            #versioning
+            #sequences
+            #version_field
+            #timestamps
+            #soft_delete
+            #hooks
+            #history
+
+            fn validate_fields(
+                doc: &Self,
+            ) -> Result<(), ::collection::validate::Violations> {
+                ::collection::validate::Validate::validate(doc)
+            }
         }
 
         #[derive(Clone)]
-        #vis struct #coll_struct_id (pub ::mongodb::Collection<#source_id>);
+        #vis struct #coll_struct_id (pub ::mongodb::Collection<#source_id>, pub ::mongodb::Database);
         impl ::collection::Collection for #coll_struct_id {
             type Internal = ::mongodb::Collection<#source_id>;
             type Document = #source_id;
 
-            async fn fetch<F: ::collection::Fetcher<Self::Document, Self::Internal>>(
+            fn fetch<F: ::collection::Fetcher<Self::Document, Self::Internal>>(
                 &self,
                 f: F,
-            ) -> Result<F::Output, F::Error> {
-                f.fetch(&self.0).await
+            ) -> impl ::std::future::Future<Output = Result<F::Output, F::Error>> + Send {
+                let check =
+                    ::collection::explain::check::<#source_id, _, _>(&self.1, self.0.name(), &f);
+                let fetch = f.fetch(&self.0);
+                async move {
+                    check.await;
+                    fetch.await
+                }
+            }
+        }
+        impl ::collection::mongo::ReadableCollection for #coll_struct_id {
+            fn inner(&self, _: ::collection::mongo::Token) -> &::mongodb::Collection<#source_id> {
+                &self.0
+            }
+            fn database(&self, _: ::collection::mongo::Token) -> &::mongodb::Database {
+                &self.1
+            }
+        }
+        impl ::collection::mongo::WritableCollection for #coll_struct_id {}
+
+        impl #coll_struct_id {
+            pub const NAME: &'static str = #db_coll;
+
+            #finders
+
+            pub fn new(db: &::mongodb::Database) -> Self {
+                Self(db.collection(Self::NAME), db.clone())
+            }
+
+            /// A handle which can not be written through, reading from secondaries when possible.
+            pub fn read_only(&self) -> ::collection::mongo::ReadOnly<#source_id> {
+                ::collection::mongo::ReadOnly::new(&self.1, &self.0, None)
+            }
+
+            pub fn read_only_with(
+                &self,
+                criteria: ::mongodb::options::SelectionCriteria,
+            ) -> ::collection::mongo::ReadOnly<#source_id> {
+                ::collection::mongo::ReadOnly::new(&self.1, &self.0, Some(criteria))
+            }
+
+            #bulk
+
+            pub async fn watch<S: ::collection::watch::ResumeTokenStore>(
+                &self,
+                filter: ::collection::watch::ChangeFilter,
+                store: S,
+            ) -> Result<::collection::watch::ChangeEvents<#source_id, S>, S::Error> {
+                ::collection::watch::ChangeEvents::open(&self.0, filter, store).await
+            }
+
+            /// Brings every stored document up to the current schema version.
+            pub async fn migrate(
+                &self,
+                progress: &::mongodb::Collection<::mongodb::bson::Document>,
+            ) -> Result<u64, ::collection::Error> {
+                ::collection::migrate::run(&self.0, progress).await
+            }
+
+            /// Installs the `$jsonSchema` of the document as the validator of this collection.
+            pub async fn apply_json_schema(
+                &self,
+                level: ::mongodb::options::ValidationLevel,
+                action: ::mongodb::options::ValidationAction,
+            ) -> ::mongodb::error::Result<()> {
+                ::collection::schema::apply::<#source_id>(&self.1, self.0.name(), level, action)
+                    .await
             }
         }
     }
     .into()
 }
+
+#[proc_macro_error]
+#[proc_macro_derive(JsonSchema)]
+pub fn json_schema(input: Ts1) -> Ts1 {
+    let item = parse_macro_input!(input as DeriveInput);
+
+    schema::derive(&item, &HashMap::new()).into()
+}
+
+/// Derives `Validate` from the `#[coll(validate(...))]` rules of the fields, for structs nested in
+/// documents.
+#[proc_macro_error]
+#[proc_macro_derive(Validate, attributes(coll))]
+pub fn validate(input: Ts1) -> Ts1 {
+    let item = parse_macro_input!(input as DeriveInput);
+
+    let rules = validate::parse(&item);
+    validate::derive(&item, &rules).into()
+}
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use proc_macro2::TokenStream;
+use proc_macro_error::abort;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::spanned::Spanned;
+use syn::{Attribute, DeriveInput, Fields, Lit, Meta, NestedMeta};
+
+#[derive(Default)]
+pub struct SerdeAttrs {
+    pub rename: Option<String>,
+    pub rename_all: Option<String>,
+    pub skip: bool,
+    pub default: bool,
+    pub flatten: bool,
+    pub tagged: bool,
+}
+
+pub fn serde_attrs(attrs: &[Attribute]) -> SerdeAttrs {
+    let mut out = SerdeAttrs::default();
+
+    let nested = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("serde"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::List(list)) => Some(list.nested),
+            _ => None,
+        })
+        .flatten();
+
+    for meta in nested {
+        let NestedMeta::Meta(meta) = meta else {
+            continue;
+        };
+        let Some(key) = meta.path().get_ident().map(ToString::to_string) else {
+            continue;
+        };
+
+        // rename(serialize = "..") names the stored field, which is what the schema describes
+        let value = match &meta {
+            Meta::NameValue(nv) => match &nv.lit {
+                Lit::Str(s) => Some(s.value()),
+                _ => None,
+            },
+            Meta::List(list) => list.nested.iter().find_map(|n| match n {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("serialize") => {
+                    match &nv.lit {
+                        Lit::Str(s) => Some(s.value()),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            }),
+            Meta::Path(_) => None,
+        };
+
+        match key.as_str() {
+            "rename" => out.rename = value,
+            "rename_all" => out.rename_all = value,
+            "skip" | "skip_serializing" | "skip_deserializing" => out.skip = true,
+            "default" | "skip_serializing_if" => out.default = true,
+            "flatten" => out.flatten = true,
+            "tag" | "content" | "untagged" => out.tagged = true,
+            _ => {}
+        }
+    }
+
+    out
+}
+
+fn split_words(name: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+
+    for c in name.chars() {
+        if c == '_' {
+            words.push(std::mem::take(&mut current));
+        } else if c.is_uppercase() && !current.is_empty() {
+            words.push(std::mem::take(&mut current));
+            current.push(c);
+        } else {
+            current.push(c);
+        }
+    }
+    words.push(current);
+
+    words
+        .into_iter()
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Applies a serde `rename_all` rule. `variant` selects serde's handling of PascalCase variant
+/// names, where `lowercase` and `UPPERCASE` do not introduce separators.
+pub fn rename(rule: &str, name: &str, variant: bool, loc: proc_macro2::Span) -> String {
+    let words = split_words(name);
+
+    match rule {
+        "lowercase" if variant => name.to_lowercase(),
+        "UPPERCASE" if variant => name.to_uppercase(),
+        "lowercase" | "snake_case" => words.join("_"),
+        "UPPERCASE" | "SCREAMING_SNAKE_CASE" => words.join("_").to_uppercase(),
+        "kebab-case" => words.join("-"),
+        "SCREAMING-KEBAB-CASE" => words.join("-").to_uppercase(),
+        "PascalCase" => words.iter().map(|w| capitalize(w)).collect(),
+        "camelCase" => {
+            let pascal = words.iter().map(|w| capitalize(w)).collect::<String>();
+            let mut chars = pascal.chars();
+            match chars.next() {
+                Some(c) => c.to_lowercase().chain(chars).collect(),
+                None => pascal,
+            }
+        }
+        _ => abort!(loc, "Unknown serde rename rule {}", rule),
+    }
+}
+
+/// The name of a named field once serialized.
+pub fn field_name(field: &syn::Field, renamed: Option<String>, rename_all: Option<&str>) -> String {
+    let ident = field.ident.as_ref().unwrap().unraw().to_string();
+
+    match (renamed, rename_all) {
+        (Some(name), _) => name,
+        (None, Some(rule)) => rename(rule, &ident, false, field.span()),
+        (None, None) => ident,
+    }
+}
+
+/// Fields with `constraints`, by identifier, have them added to their schema.
+fn object(
+    fields: &syn::FieldsNamed,
+    rename_all: Option<&str>,
+    constraints: &HashMap<String, TokenStream>,
+) -> TokenStream {
+    let mut inserts = TokenStream::new();
+
+    for field in fields.named.iter() {
+        let attrs = serde_attrs(&field.attrs);
+        if attrs.skip {
+            continue;
+        }
+        if attrs.flatten {
+            abort!(
+                field.span(),
+                "Flattened fields are not supported in json schemas"
+            );
+        }
+
+        let name = field_name(field, attrs.rename, rename_all);
+        let ty = &field.ty;
+        let optional = attrs.default;
+        let schema = match constraints.get(&field.ident.as_ref().unwrap().to_string()) {
+            Some(constraints) => quote! {{
+                let mut field_schema = <#ty as ::collection::schema::JsonSchema>::json_schema();
+                #constraints
+                field_schema
+            }},
+            None => quote! { <#ty as ::collection::schema::JsonSchema>::json_schema() },
+        };
+
+        inserts.extend(quote! {
+            properties.insert(#name, #schema);
+            if <#ty as ::collection::schema::JsonSchema>::REQUIRED && !#optional {
+                required.push(#name.into());
+            }
+        });
+    }
+
+    quote! {{
+        let mut properties = ::mongodb::bson::Document::new();
+        let mut required = ::std::vec::Vec::<::mongodb::bson::Bson>::new();
+        #inserts
+
+        let mut schema = ::mongodb::bson::doc! { "bsonType": "object", "properties": properties };
+        if !required.is_empty() {
+            schema.insert("required", required);
+        }
+        schema
+    }}
+}
+
+fn unnamed(fields: &syn::FieldsUnnamed) -> TokenStream {
+    if fields.unnamed.len() == 1 {
+        let ty = &fields.unnamed.first().unwrap().ty;
+        return quote! { <#ty as ::collection::schema::JsonSchema>::json_schema() };
+    }
+
+    let items = fields.unnamed.iter().map(|f| {
+        let ty = &f.ty;
+        quote! { <#ty as ::collection::schema::JsonSchema>::json_schema() }
+    });
+    quote! {{
+        let items: ::std::vec::Vec<::mongodb::bson::Bson> = vec![#(#items.into()),*];
+        ::mongodb::bson::doc! { "bsonType": "array", "items": items }
+    }}
+}
+
+fn fields_schema(
+    fields: &Fields,
+    rename_all: Option<&str>,
+    constraints: &HashMap<String, TokenStream>,
+) -> TokenStream {
+    match fields {
+        Fields::Named(named) => object(named, rename_all, constraints),
+        Fields::Unnamed(fields) => unnamed(fields),
+        Fields::Unit => quote! { ::mongodb::bson::doc! { "bsonType": "null" } },
+    }
+}
+
+/// The `JsonSchema` impl of `item`, with `constraints` added to the schemas of its fields.
+pub fn derive(item: &DeriveInput, constraints: &HashMap<String, TokenStream>) -> TokenStream {
+    let container = serde_attrs(&item.attrs);
+
+    let body = match &item.data {
+        syn::Data::Struct(data) => {
+            fields_schema(&data.fields, container.rename_all.as_deref(), constraints)
+        }
+        syn::Data::Enum(data) => {
+            if container.tagged {
+                abort!(
+                    item.span(),
+                    "Only externally tagged enums are supported in json schemas"
+                );
+            }
+
+            let mut units = Vec::new();
+            let mut variants = Vec::new();
+
+            for variant in data.variants.iter() {
+                let attrs = serde_attrs(&variant.attrs);
+                if attrs.skip {
+                    continue;
+                }
+
+                let ident = variant.ident.unraw().to_string();
+                let name = match (attrs.rename, container.rename_all.as_deref()) {
+                    (Some(name), _) => name,
+                    (None, Some(rule)) => rename(rule, &ident, true, variant.span()),
+                    (None, None) => ident,
+                };
+
+                if let Fields::Unit = variant.fields {
+                    units.push(name);
+                    continue;
+                }
+
+                let inner = fields_schema(
+                    &variant.fields,
+                    attrs.rename_all.as_deref(),
+                    &HashMap::new(),
+                );
+                variants.push(quote! {{
+                    let inner: ::mongodb::bson::Document = #inner;
+                    ::mongodb::bson::doc! {
+                        "bsonType": "object",
+                        "required": [#name],
+                        "properties": { #name: inner },
+                        "additionalProperties": false,
+                    }
+                }});
+            }
+
+            let units = (!units.is_empty()).then(|| {
+                quote! { ::mongodb::bson::doc! { "enum": [#(#units),*] } }
+            });
+
+            if variants.is_empty() && units.is_none() {
+                // No variant can be stored, so neither can any value.
+                quote! { ::mongodb::bson::doc! { "not": {} } }
+            } else if variants.is_empty() {
+                quote! { #units }
+            } else {
+                let units = units.into_iter();
+                quote! {{
+                    let variants: ::std::vec::Vec<::mongodb::bson::Bson> =
+                        vec![#(#units.into(),)* #(#variants.into()),*];
+                    ::mongodb::bson::doc! { "oneOf": variants }
+                }}
+            }
+        }
+        syn::Data::Union(_) => abort!(item.span(), "Json schemas can not be derived for unions"),
+    };
+
+    let ident = &item.ident;
+    let mut generics = item.generics.clone();
+    let params = generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .collect::<Vec<_>>();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#param: ::collection::schema::JsonSchema));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::collection::schema::JsonSchema for #ident #ty_generics #where_clause {
+            fn json_schema() -> ::mongodb::bson::Document {
+                #body
+            }
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! The `#[coll(validate(...))]` rules of fields, checked by `collection::validate::Validate`.
+
+use std::collections::HashMap;
+
+use proc_macro2::TokenStream;
+use proc_macro_error::{abort, emit_error};
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{DeriveInput, Expr, Lit, RangeLimits};
+
+use crate::attr::Args;
+use crate::schema;
+
+const RULES: &[&str] = &["length", "email", "range", "regex", "nested"];
+
+enum Rule {
+    Length(Expr),
+    Email,
+    Range(Expr),
+    Regex(String),
+    Nested,
+}
+
+pub struct FieldRules {
+    field: syn::Field,
+    name: String,
+    rules: Vec<Rule>,
+}
+
+fn range(expr: Expr) -> Expr {
+    if !matches!(expr, Expr::Range(_)) {
+        emit_error!(expr.span(), "Expected a range like `1..=64`");
+    }
+    expr
+}
+
+/// The rules declared on the fields of `item`.
+pub fn parse(item: &DeriveInput) -> Vec<FieldRules> {
+    let syn::Data::Struct(data) = &item.data else {
+        abort!(
+            item.span(),
+            "Validation rules are only supported on structs"
+        );
+    };
+    let rename_all = schema::serde_attrs(&item.attrs).rename_all;
+
+    let mut out = Vec::new();
+    for field in &data.fields {
+        let mut rules = Vec::new();
+
+        for arg in Args::from_attrs(&field.attrs).0 {
+            if arg.name() != "validate" {
+                continue;
+            }
+            if field.ident.is_none() {
+                abort!(arg.span(), "Validation rules require named fields");
+            }
+
+            for rule in arg.list().0 {
+                rule.no_vis();
+                match rule.name().as_str() {
+                    "length" => rules.push(Rule::Length(range(rule.list_expr()))),
+                    "range" => rules.push(Rule::Range(range(rule.list_expr()))),
+                    "email" => {
+                        if rule.flag() {
+                            rules.push(Rule::Email);
+                        }
+                    }
+                    "nested" => {
+                        if rule.flag() {
+                            rules.push(Rule::Nested);
+                        }
+                    }
+                    "regex" => {
+                        let pattern = rule.string();
+                        if let Err(e) = regex::Regex::new(&pattern) {
+                            emit_error!(rule.span(), "Invalid regex: {}", e);
+                        }
+                        rules.push(Rule::Regex(pattern));
+                    }
+                    _ => rule.unknown("validation rule", RULES),
+                }
+            }
+        }
+
+        if !rules.is_empty() {
+            let name = schema::field_name(
+                field,
+                schema::serde_attrs(&field.attrs).rename,
+                rename_all.as_deref(),
+            );
+            out.push(FieldRules {
+                field: field.clone(),
+                name,
+                rules,
+            });
+        }
+    }
+
+    out
+}
+
+/// `1..=64` as written, for messages.
+fn text(expr: &Expr) -> String {
+    quote!(#expr).to_string().replace(' ', "")
+}
+
+fn violation(rule: &str, message: String) -> TokenStream {
+    quote! {
+        out.push(::collection::validate::Violation {
+            path: path.clone(),
+            rule: #rule,
+            message: #message.to_owned(),
+        });
+    }
+}
+
+fn check(field: &syn::Field, rule: &Rule) -> TokenStream {
+    let ident = field.ident.as_ref().unwrap();
+
+    match rule {
+        Rule::Length(expr) => {
+            let push = violation("length", format!("length must be in {}", text(expr)));
+            quote! {
+                if let Some(len) = ::collection::validate::Length::length(&self.#ident) {
+                    if !(#expr).contains(&len) {
+                        #push
+                    }
+                }
+            }
+        }
+        Rule::Range(expr) => {
+            let push = violation("range", format!("must be in {}", text(expr)));
+            quote! {
+                if let Some(value) = ::collection::validate::Scalar::value(&self.#ident) {
+                    if !(#expr).contains(value) {
+                        #push
+                    }
+                }
+            }
+        }
+        Rule::Email => {
+            let push = violation("email", "must be an email address".to_owned());
+            quote! {
+                if let Some(text) = ::collection::validate::Text::text(&self.#ident) {
+                    if !::collection::validate::is_email(text) {
+                        #push
+                    }
+                }
+            }
+        }
+        Rule::Regex(pattern) => {
+            let push = violation("regex", format!("must match {}", pattern));
+            quote! {
+                if let Some(text) = ::collection::validate::Text::text(&self.#ident) {
+                    static RE: ::std::sync::OnceLock<::collection::validate::Regex> =
+                        ::std::sync::OnceLock::new();
+                    if !RE.get_or_init(|| ::collection::validate::Regex::new(#pattern).unwrap()).is_match(text) {
+                        #push
+                    }
+                }
+            }
+        }
+        Rule::Nested => quote! {
+            ::collection::validate::Validate::violations(&self.#ident, &path, out);
+        },
+    }
+}
+
+/// The `Validate` impl of `item` checking `fields`.
+pub fn derive(item: &DeriveInput, fields: &[FieldRules]) -> TokenStream {
+    let checks = fields.iter().map(|FieldRules { field, name, rules }| {
+        let checks = rules.iter().map(|rule| check(field, rule));
+        quote! {{
+            let path = ::collection::validate::join(path, #name);
+            #({ #checks })*
+        }}
+    });
+
+    let ident = &item.ident;
+    let (impl_generics, ty_generics, where_clause) = item.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::collection::validate::Validate for #ident #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn violations(
+                &self,
+                path: &str,
+                out: &mut ::std::vec::Vec<::collection::validate::Violation>,
+            ) {
+                #(#checks)*
+            }
+        }
+    }
+}
+
+fn is_float(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(lit) => matches!(lit.lit, Lit::Float(_)),
+        Expr::Unary(unary) => is_float(&unary.expr),
+        Expr::Paren(paren) => is_float(&paren.expr),
+        _ => false,
+    }
+}
+
+fn number(expr: &Expr) -> TokenStream {
+    if is_float(expr) {
+        quote!(((#expr) as f64))
+    } else {
+        quote!(((#expr) as i64))
+    }
+}
+
+/// Statements adding the rules of a field to its `field_schema`.
+fn constraints(field: &syn::Field, rules: &[Rule]) -> TokenStream {
+    let mut out = TokenStream::new();
+    let mut insert = |key: &str, value: TokenStream| {
+        out.extend(quote! { field_schema.insert(#key, #value); });
+    };
+
+    for rule in rules {
+        match rule {
+            Rule::Length(Expr::Range(r)) => {
+                let (min, max) = if crate::is_string(&field.ty) {
+                    ("minLength", "maxLength")
+                } else {
+                    ("minItems", "maxItems")
+                };
+                if let Some(start) = &r.from {
+                    insert(min, quote!(((#start) as i64)));
+                }
+                match (&r.to, r.limits) {
+                    (Some(end), RangeLimits::Closed(_)) => insert(max, quote!(((#end) as i64))),
+                    (Some(end), RangeLimits::HalfOpen(_)) => {
+                        insert(max, quote!(((#end) as i64 - 1)))
+                    }
+                    (None, _) => {}
+                }
+            }
+            Rule::Range(Expr::Range(r)) => {
+                if let Some(start) = &r.from {
+                    insert("minimum", number(start));
+                }
+                if let Some(end) = &r.to {
+                    insert("maximum", number(end));
+                    if matches!(r.limits, RangeLimits::HalfOpen(_)) {
+                        insert("exclusiveMaximum", quote!(true));
+                    }
+                }
+            }
+            Rule::Regex(pattern) => insert("pattern", quote!(#pattern)),
+            // A schema holds a single pattern, an explicit regex takes precedence.
+            Rule::Email if !rules.iter().any(|r| matches!(r, Rule::Regex(_))) => {
+                insert("pattern", quote!(::collection::validate::EMAIL))
+            }
+            _ => {}
+        }
+    }
+
+    out
+}
+
+/// The schema constraints mirroring `fields`, by field identifier.
+pub fn schema(fields: &[FieldRules]) -> HashMap<String, TokenStream> {
+    fields
+        .iter()
+        .map(|f| {
+            let ident = f.field.ident.as_ref().unwrap().to_string();
+            (ident, constraints(&f.field, &f.rules))
+        })
+        .filter(|(_, c)| !c.is_empty())
+        .collect()
+}
@@ -0,0 +1,471 @@
+//! Bulk writes of mixed operations, split into as few commands as the server limits allow.
+
+use std::mem;
+
+use mongodb::bson::{self, doc, Bson, DateTime};
+
+use crate::error::{duplicate_key, DuplicateKey};
+use crate::index::Index;
+use crate::mongo::{WritableCollection, TOKEN};
+use crate::soft_delete::Scope;
+use crate::{concurrency, id, migrate, Collection, Document, Error};
+use crate::{context, sequence};
+
+/// The server's `maxWriteBatchSize`.
+const MAX_COUNT: usize = 100_000;
+/// The server's 16MB `maxMessageSizeBytes`.
+const MAX_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Kind {
+    fn command(self) -> (&'static str, &'static str) {
+        match self {
+            Kind::Insert => ("insert", "documents"),
+            Kind::Update => ("update", "updates"),
+            Kind::Delete => ("delete", "deletes"),
+        }
+    }
+}
+
+struct Op {
+    kind: Kind,
+    stmt: Result<bson::Document, Error>,
+}
+
+/// A failed operation, by its position among the operations added to the [`Bulk`]. `I` is the
+/// generated index enum of the document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkWriteError<I> {
+    pub index: usize,
+    /// The server's error code, `None` if the operation could not be encoded.
+    pub code: Option<i32>,
+    pub message: String,
+    /// The declared unique index the operation would have duplicated a key in, if that's why it
+    /// failed.
+    pub duplicate: Option<DuplicateKey<I>>,
+}
+
+impl<I: Index> BulkWriteError<I> {
+    /// The error of the operation at `index`, from an entry of the reply's `writeErrors`.
+    fn from_reply(index: usize, err: &bson::Document) -> Self {
+        let code = err.get_i32("code").ok();
+        let message = err.get_str("errmsg").unwrap_or_default().to_owned();
+        let duplicate = (code == Some(11000))
+            .then(|| duplicate_key::<I>(&message))
+            .flatten()
+            .map(|(index, key)| DuplicateKey { index, key });
+
+        Self {
+            index,
+            code,
+            message,
+            duplicate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BulkResult<I> {
+    pub inserted: u64,
+    pub matched: u64,
+    pub modified: u64,
+    pub deleted: u64,
+    /// Operation index and `_id` of every upserted document.
+    pub upserted: Vec<(usize, Bson)>,
+    pub errors: Vec<BulkWriteError<I>>,
+}
+
+impl<I> Default for BulkResult<I> {
+    fn default() -> Self {
+        Self {
+            inserted: 0,
+            matched: 0,
+            modified: 0,
+            deleted: 0,
+            upserted: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+/// The result of a bulk write to `C`.
+type ResultOf<C> = BulkResult<<<C as Collection>::Document as Document>::Index>;
+
+/// A builder of bulk writes, see the generated `bulk` method.
+///
+/// Ordered bulks (the default) stop at the first failing operation. Unordered ones carry on past
+/// failures and may be reordered to need fewer commands. Document hooks are not run. The
+/// collection is told once the bulk ran, see [`WritableCollection::written`].
+///
+/// Documents with a [`history`](crate::history) can't be written in bulk, since nothing would be
+/// recorded; the generated collection has no `bulk` method then, and this fails to compile:
+///
+/// ```ignore
+/// Bulk::new(&audited)
+/// ```
+pub struct Bulk<'a, C: WritableCollection> {
+    coll: &'a C,
+    ops: Vec<Op>,
+    ordered: bool,
+}
+
+impl<'a, C: WritableCollection> Bulk<'a, C> {
+    pub fn new(coll: &'a C) -> Self {
+        const { assert!(!C::Document::HISTORY, "bulk writes don't record history") };
+        Self {
+            coll,
+            ops: Vec::new(),
+            ordered: true,
+        }
+    }
+
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Inserts the document, generating its `_id` if it has none. Missing sequence fields are
+    /// numbered when the bulk is run.
+    pub fn insert(self, doc: &C::Document) -> Self {
+        let stmt = Self::encode(doc).map(|mut raw| {
+            id::ensure_id::<C::Document>(&mut raw);
+            C::Document::TIMESTAMPS.on_insert(&mut raw, DateTime::now());
+            raw
+        });
+        self.push(Kind::Insert, stmt)
+    }
+
+    pub fn update_one(self, filter: bson::Document, update: bson::Document) -> Self {
+        self.update(filter, update, false)
+    }
+
+    pub fn update_many(self, filter: bson::Document, update: bson::Document) -> Self {
+        self.update(filter, update, true)
+    }
+
+    /// Replaces the stored document with the same `_id`. Versioned documents which moved on since
+    /// they were read are not matched.
+    pub fn replace(self, doc: &C::Document) -> Self {
+        self.replace_by_id(doc, false)
+    }
+
+    /// Replaces the stored document with the same `_id`, inserting it if there is none.
+    pub fn upsert(self, doc: &C::Document) -> Self {
+        self.replace_by_id(doc, true)
+    }
+
+    /// Deletes the first matching document, or stamps it as deleted if the document has soft
+    /// delete, which counts as an update in the result.
+    pub fn delete_one(self, filter: bson::Document) -> Self {
+        self.delete(filter, false)
+    }
+
+    pub fn delete_many(self, filter: bson::Document) -> Self {
+        self.delete(filter, true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    fn update(self, filter: bson::Document, mut update: bson::Document, multi: bool) -> Self {
+        concurrency::inc::<C::Document>(&mut update);
+        C::Document::TIMESTAMPS.on_update(&mut update, DateTime::now());
+        let stmt = doc! { "q": filter, "u": update, "multi": multi };
+        self.push(Kind::Update, Ok(stmt))
+    }
+
+    fn delete(self, mut filter: bson::Document, multi: bool) -> Self {
+        let Some(field) = C::Document::SOFT_DELETE else {
+            let limit = if multi { 0 } else { 1 };
+            return self.push(Kind::Delete, Ok(doc! { "q": filter, "limit": limit }));
+        };
+
+        Scope::Active.apply::<C::Document>(&mut filter);
+        self.update(filter, doc! { "$currentDate": { field: true } }, multi)
+    }
+
+    fn replace_by_id(self, doc: &C::Document, upsert: bool) -> Self {
+        let stmt = Self::encode(doc).map(|mut raw| {
+            let filter = if upsert {
+                doc! { "_id": raw.get("_id").cloned().unwrap_or(Bson::Null) }
+            } else {
+                concurrency::filter::<C::Document>(&raw)
+            };
+            concurrency::bump::<C::Document>(&mut raw);
+
+            let stamps = C::Document::TIMESTAMPS;
+            let update = if upsert {
+                stamps.upsert(raw, DateTime::now())
+            } else {
+                stamps.on_replace(&mut raw, DateTime::now());
+                raw
+            };
+            doc! { "q": filter, "u": update, "upsert": upsert }
+        });
+        self.push(Kind::Update, stmt)
+    }
+
+    fn encode(doc: &C::Document) -> Result<bson::Document, Error> {
+        C::Document::validate_fields(doc)?;
+        migrate::encode(doc)
+    }
+
+    fn push(mut self, kind: Kind, stmt: Result<bson::Document, Error>) -> Self {
+        self.ops.push(Op { kind, stmt });
+        self
+    }
+
+    /// Runs every operation. Failures of individual operations are reported in
+    /// [`BulkResult::errors`]; an `Err` means a whole command failed, e.g. on a network error, and
+    /// operations after it were not attempted.
+    pub async fn run(mut self) -> Result<ResultOf<C>, Error> {
+        let mut result = BulkResult::default();
+
+        let mut ops = Vec::with_capacity(self.ops.len());
+        for (index, op) in mem::take(&mut self.ops).into_iter().enumerate() {
+            match op.stmt {
+                Ok(stmt) => ops.push((index, op.kind, stmt)),
+                Err(e) => {
+                    result.errors.push(BulkWriteError {
+                        index,
+                        code: None,
+                        message: e.to_string(),
+                        duplicate: None,
+                    });
+
+                    if self.ordered {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let inserts = ops
+            .iter_mut()
+            .filter(|(_, kind, _)| *kind == Kind::Insert)
+            .map(|(_, _, stmt)| stmt)
+            .collect();
+        sequence::assign::<C::Document, _>(&self.coll.sequences(), inserts).await?;
+
+        if !self.ordered {
+            ops.sort_by_key(|(_, kind, _)| *kind);
+        }
+
+        let sent = self.send(ops, &mut result).await;
+        self.coll.written();
+        sent?;
+
+        result.errors.sort_by_key(|e| e.index);
+        Ok(result)
+    }
+
+    /// Sends the operations in as few commands as fit, collecting their outcome in `result`.
+    async fn send(
+        &self,
+        ops: Vec<(usize, Kind, bson::Document)>,
+        result: &mut ResultOf<C>,
+    ) -> Result<(), Error> {
+        let name = self.coll.inner(TOKEN).name();
+        for (kind, batch) in batches(ops, name, self.ordered, MAX_COUNT, MAX_BYTES) {
+            let (indexes, stmts): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+
+            let cmd = command(kind, name, self.ordered, stmts);
+            let reply = context::run_command(self.coll.database(TOKEN), cmd).await?;
+
+            let n = count(&reply, "n");
+            match kind {
+                Kind::Insert => result.inserted += n,
+                Kind::Delete => result.deleted += n,
+                Kind::Update => {
+                    let upserted = reply.get_array("upserted").map_or(&[][..], Vec::as_slice);
+                    for up in upserted {
+                        let Some(up) = up.as_document() else {
+                            continue;
+                        };
+                        let at = count(up, "index") as usize;
+                        let id = up.get("_id").cloned().unwrap_or(Bson::Null);
+                        result.upserted.push((indexes[at], id));
+                    }
+
+                    result.matched += n - upserted.len() as u64;
+                    result.modified += count(&reply, "nModified");
+                }
+            }
+
+            let failed = reply
+                .get_array("writeErrors")
+                .map_or(&[][..], Vec::as_slice);
+            for err in failed {
+                let Some(err) = err.as_document() else {
+                    continue;
+                };
+                let index = indexes[count(err, "index") as usize];
+                result.errors.push(BulkWriteError::from_reply(index, err));
+            }
+
+            if self.ordered && !failed.is_empty() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+type Stmt = (usize, bson::Document);
+
+fn command(kind: Kind, name: &str, ordered: bool, stmts: Vec<bson::Document>) -> bson::Document {
+    let (command, field) = kind.command();
+    doc! { command: name, field: stmts, "ordered": ordered }
+}
+
+fn size(doc: &bson::Document) -> usize {
+    bson::to_vec(doc).map_or(0, |raw| raw.len())
+}
+
+/// Groups consecutive statements of the same kind into batches whose commands stay within the
+/// given limits. Each statement also costs its array element's type byte and index key.
+fn batches(
+    ops: Vec<(usize, Kind, bson::Document)>,
+    name: &str,
+    ordered: bool,
+    max_count: usize,
+    max_bytes: usize,
+) -> Vec<(Kind, Vec<Stmt>)> {
+    let mut out: Vec<(Kind, Vec<Stmt>)> = Vec::new();
+    let mut bytes = 0;
+
+    for (index, kind, stmt) in ops {
+        let element = |at: usize| 1 + at.to_string().len() + 1 + size(&stmt);
+
+        match out.last_mut() {
+            Some((k, batch))
+                if *k == kind
+                    && batch.len() < max_count
+                    && bytes + element(batch.len()) <= max_bytes =>
+            {
+                bytes += element(batch.len());
+                batch.push((index, stmt));
+            }
+            _ => {
+                bytes = size(&command(kind, name, ordered, Vec::new())) + element(0);
+                out.push((kind, vec![(index, stmt)]));
+            }
+        }
+    }
+
+    out
+}
+
+fn count(reply: &bson::Document, key: &str) -> u64 {
+    match reply.get(key) {
+        Some(Bson::Int32(n)) => *n as u64,
+        Some(Bson::Int64(n)) => *n as u64,
+        Some(Bson::Double(n)) => *n as u64,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+    use mongodb::IndexModel;
+
+    use super::{batches, command, size, BulkWriteError, Kind};
+    use crate::error::DuplicateKey;
+    use crate::index::Index;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum TestIndex {
+        Email,
+    }
+
+    impl Index for TestIndex {
+        const ALL: &'static [Self] = &[Self::Email];
+
+        fn name(self) -> &'static str {
+            "email"
+        }
+
+        fn from_name(name: &str) -> Option<Self> {
+            (name == "email").then_some(Self::Email)
+        }
+
+        fn model(self) -> IndexModel {
+            IndexModel::default()
+        }
+    }
+
+    #[test]
+    fn it_splits_batches() {
+        let ops = vec![
+            (0, Kind::Insert, doc! { "a": 1 }),
+            (1, Kind::Insert, doc! { "a": 2 }),
+            (2, Kind::Insert, doc! { "a": 3 }),
+            (3, Kind::Delete, doc! { "q": {}, "limit": 1 }),
+            (4, Kind::Insert, doc! { "a": "a much longer value" }),
+        ];
+
+        let split = batches(ops, "c", true, 2, 1024)
+            .into_iter()
+            .map(|(kind, batch)| (kind, batch.into_iter().map(|(i, _)| i).collect()))
+            .collect::<Vec<(Kind, Vec<usize>)>>();
+
+        assert_eq!(
+            split,
+            vec![
+                (Kind::Insert, vec![0, 1]),
+                (Kind::Insert, vec![2]),
+                (Kind::Delete, vec![3]),
+                (Kind::Insert, vec![4]),
+            ]
+        );
+
+        // The whole command of two statements, exactly at the limit and one byte over it.
+        let stmt = doc! { "a": 1 };
+        let two = size(&command(Kind::Insert, "c", true, vec![stmt.clone(); 2]));
+        let lens = |max_bytes| {
+            let ops = vec![(0, Kind::Insert, stmt.clone()); 3];
+            batches(ops, "c", true, 10, max_bytes)
+                .iter()
+                .map(|(_, b)| b.len())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(lens(two), [2, 1]);
+        assert_eq!(lens(two - 1), [1, 1, 1]);
+    }
+
+    #[test]
+    fn it_maps_duplicate_keys_to_indexes() {
+        let err = doc! {
+            "index": 0,
+            "code": 11000,
+            "errmsg": r#"E11000 duplicate key error collection: app.users index: email dup key: { email: "a" }"#,
+        };
+        let err = BulkWriteError::<TestIndex>::from_reply(3, &err);
+        assert_eq!(err.index, 3);
+        assert_eq!(
+            err.duplicate,
+            Some(DuplicateKey {
+                index: TestIndex::Email,
+                key: r#"{ email: "a" }"#.to_owned(),
+            })
+        );
+
+        let err = doc! { "index": 0, "code": 121, "errmsg": "Document failed validation" };
+        let err = BulkWriteError::<TestIndex>::from_reply(0, &err);
+        assert_eq!(err.code, Some(121));
+        assert_eq!(err.duplicate, None);
+    }
+}
@@ -0,0 +1,423 @@
+//! Read-through caching of single document lookups.
+//!
+//! [`Cached`] wraps a collection and serves [`ById`] and [`FindOne`] from a bounded LRU whose
+//! entries expire after a TTL. Writes through the wrapper invalidate the affected entries, bulk
+//! writes every entry, and [`Cached::invalidate_on_changes`] extends that to writes made by anyone
+//! else.
+
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use mongodb::bson::{self, Bson};
+use mongodb::change_stream::event::OperationType;
+
+use crate::fetchers::{ById, FindOne};
+use crate::mongo::{ReadableCollection, Token, WritableCollection, WriteResult, TOKEN};
+use crate::sequence::Sequences;
+use crate::tracking::Tracked;
+use crate::{id, migrate, Collection, Error, Fetcher, Id};
+
+/// Where a cached fetcher's result is stored.
+pub enum CacheKey {
+    /// The [`id::key`] of the document's `_id`.
+    Id(String),
+    Query(String),
+}
+
+/// A fetcher of at most one document whose result may be cached.
+pub trait Cacheable<D, I>: Fetcher<D, I, Output = Option<D>, Error = Error> {
+    fn cache_key(&self) -> CacheKey;
+}
+
+impl<D, I, K> Cacheable<D, I> for ById<K>
+where
+    ById<K>: Fetcher<D, I, Output = Option<D>, Error = Error>,
+    K: Into<Bson> + Clone,
+{
+    fn cache_key(&self) -> CacheKey {
+        CacheKey::Id(id::key(&self.0.clone().into()))
+    }
+}
+
+impl<D, I> Cacheable<D, I> for FindOne
+where
+    FindOne: Fetcher<D, I, Output = Option<D>, Error = Error>,
+{
+    fn cache_key(&self) -> CacheKey {
+        CacheKey::Query(self.0.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub len: usize,
+}
+
+/// A collection whose single document lookups are cached, see [`ReadableCollection::cached`].
+pub struct Cached<C: ReadableCollection> {
+    coll: C,
+    state: Arc<Mutex<State<C::Document>>>,
+}
+
+impl<C: ReadableCollection + Clone> Clone for Cached<C> {
+    fn clone(&self) -> Self {
+        Self {
+            coll: self.coll.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+struct State<D> {
+    ids: Lru<Option<D>>,
+    queries: Lru<Option<D>>,
+    /// Bumped on every invalidation, so lookups racing a write don't store what they read.
+    generation: u64,
+    stats: CacheStats,
+}
+
+impl<D> State<D> {
+    fn lru<'k>(&mut self, key: &'k CacheKey) -> (&mut Lru<Option<D>>, &'k str) {
+        match key {
+            CacheKey::Id(k) => (&mut self.ids, k),
+            CacheKey::Query(k) => (&mut self.queries, k),
+        }
+    }
+
+    fn invalidate(&mut self, id: Option<&Bson>) {
+        self.generation += 1;
+        self.queries.clear();
+
+        match id {
+            Some(id) => self.ids.remove(&id::key(id)),
+            None => self.ids.clear(),
+        }
+    }
+}
+
+impl<C: ReadableCollection> Cached<C> {
+    /// Caches up to `capacity` documents by id, and as many query results, each for `ttl`.
+    pub fn new(coll: C, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            coll,
+            state: Arc::new(Mutex::new(State {
+                ids: Lru::new(capacity, ttl),
+                queries: Lru::new(capacity, ttl),
+                generation: 0,
+                stats: CacheStats::default(),
+            })),
+        }
+    }
+
+    /// Serves the fetcher from the cache, fetching and storing its result on a miss. Hits are
+    /// returned as loaded, without running `after_load` again.
+    pub async fn get<F: Cacheable<C::Document, C::Internal>>(
+        &self,
+        f: F,
+    ) -> Result<Option<C::Document>, Error>
+    where
+        C::Document: Clone,
+    {
+        let key = f.cache_key();
+
+        let generation = {
+            let mut state = self.state.lock().unwrap();
+            let (lru, k) = state.lru(&key);
+
+            if let Some(hit) = lru.get(k).cloned() {
+                state.stats.hits += 1;
+                return Ok(hit);
+            }
+
+            state.stats.misses += 1;
+            state.generation
+        };
+
+        let doc = self.coll.fetch(f).await?;
+
+        let mut state = self.state.lock().unwrap();
+        if state.generation == generation {
+            let (lru, k) = state.lru(&key);
+            let evicted = lru.insert(k.to_owned(), doc.clone());
+            state.stats.evictions += evicted as u64;
+        }
+
+        Ok(doc)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+
+        CacheStats {
+            len: state.ids.len() + state.queries.len(),
+            ..state.stats
+        }
+    }
+
+    /// Drops the cached document with the given id, or everything if `None`. Cached query
+    /// results are always dropped, as there is no telling which of them the change affects.
+    pub fn invalidate(&self, id: Option<&Bson>) {
+        self.state.lock().unwrap().invalidate(id);
+    }
+
+    /// Invalidates entries as the change stream reports writes to the collection, until the
+    /// stream is closed. Meant to be spawned alongside the cache.
+    pub async fn invalidate_on_changes(&self) -> Result<(), Error> {
+        let raw = self.coll.inner(TOKEN).clone_with_type::<bson::Document>();
+        let mut stream = raw.watch(None, None).await?;
+
+        while let Some(ev) = stream.next().await {
+            let ev = ev?;
+            let id = ev.document_key.as_ref().and_then(|key| key.get("_id"));
+
+            match ev.operation_type {
+                OperationType::Insert
+                | OperationType::Update
+                | OperationType::Replace
+                | OperationType::Delete => self.invalidate(id),
+                OperationType::Invalidate => {
+                    self.invalidate(None);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The id a write filter targets, if it targets exactly one.
+    fn filter_id(filter: &bson::Document) -> Option<&Bson> {
+        match filter.get("_id") {
+            Some(Bson::Document(op)) if op.keys().any(|k| k.starts_with('$')) => None,
+            id if filter.len() == 1 => id,
+            _ => None,
+        }
+    }
+}
+
+impl<C: ReadableCollection> Collection for Cached<C> {
+    type Internal = C::Internal;
+    type Document = C::Document;
+
+    /// Passes straight through; use [`Cached::get`] for cached lookups.
+    fn fetch<F: Fetcher<Self::Document, Self::Internal>>(
+        &self,
+        f: F,
+    ) -> impl Future<Output = Result<F::Output, F::Error>> + Send {
+        self.coll.fetch(f)
+    }
+}
+
+impl<C: ReadableCollection> ReadableCollection for Cached<C> {
+    fn inner(&self, token: Token) -> &mongodb::Collection<Self::Document> {
+        self.coll.inner(token)
+    }
+
+    fn database(&self, token: Token) -> &mongodb::Database {
+        self.coll.database(token)
+    }
+}
+
+impl<C: WritableCollection> WritableCollection for Cached<C> {
+    fn sequences(&self) -> impl Sequences + '_ {
+        self.coll.sequences()
+    }
+
+    /// Drops every entry, since any document may have changed.
+    fn written(&self) {
+        self.coll.written();
+        self.invalidate(None);
+    }
+
+    async fn insert(&self, doc: &Self::Document) -> WriteResult<Self, Id<Self::Document>> {
+        let id = self.coll.insert(doc).await?;
+        self.invalidate(Some(&id.clone().into()));
+        Ok(id)
+    }
+
+    async fn insert_many(
+        &self,
+        docs: &[Self::Document],
+    ) -> WriteResult<Self, Vec<Id<Self::Document>>> {
+        let res = self.coll.insert_many(docs).await;
+        self.invalidate(None);
+        res
+    }
+
+    async fn replace(&self, doc: &Self::Document) -> WriteResult<Self, bool> {
+        let id = migrate::encode(doc)?.get("_id").cloned();
+        let res = self.coll.replace(doc).await;
+        self.invalidate(id.as_ref());
+        res
+    }
+
+    async fn upsert(&self, doc: &Self::Document) -> WriteResult<Self, ()> {
+        let id = migrate::encode(doc)?.get("_id").cloned();
+        let res = self.coll.upsert(doc).await;
+        self.invalidate(id.as_ref());
+        res
+    }
+
+    async fn update(
+        &self,
+        filter: bson::Document,
+        update: bson::Document,
+    ) -> WriteResult<Self, bool> {
+        let id = Self::filter_id(&filter).cloned();
+        let res = self.coll.update(filter, update).await;
+        self.invalidate(id.as_ref());
+        res
+    }
+
+    async fn update_doc(
+        &self,
+        doc: &Self::Document,
+        update: bson::Document,
+    ) -> WriteResult<Self, bool> {
+        let id = migrate::encode(doc)?.get("_id").cloned();
+        let res = self.coll.update_doc(doc, update).await;
+        self.invalidate(id.as_ref());
+        res
+    }
+
+    async fn save(&self, doc: &mut Tracked<Self::Document>) -> WriteResult<Self, bool> {
+        let id = doc.original().get("_id").cloned();
+        let res = self.coll.save(doc).await;
+        self.invalidate(id.as_ref());
+        res
+    }
+
+    async fn delete(&self, filter: bson::Document) -> WriteResult<Self, bool> {
+        let id = Self::filter_id(&filter).cloned();
+        let res = self.coll.delete(filter).await;
+        self.invalidate(id.as_ref());
+        res
+    }
+
+    async fn restore(&self, filter: bson::Document) -> WriteResult<Self, bool> {
+        let id = Self::filter_id(&filter).cloned();
+        let res = self.coll.restore(filter).await;
+        self.invalidate(id.as_ref());
+        res
+    }
+}
+
+struct Entry<V> {
+    value: V,
+    at: Instant,
+    tick: u64,
+}
+
+/// Least recently used map with a TTL; `order` maps the tick of each entry's last use to its key.
+struct Lru<V> {
+    entries: HashMap<String, Entry<V>>,
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl<V> Lru<V> {
+    fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            capacity,
+            ttl,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn get(&mut self, key: &str) -> Option<&V> {
+        let expired = self.entries.get(key)?.at.elapsed() >= self.ttl;
+        if expired {
+            self.remove(key);
+            return None;
+        }
+
+        self.tick += 1;
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.tick);
+        self.order.insert(self.tick, key.to_owned());
+        entry.tick = self.tick;
+
+        Some(&entry.value)
+    }
+
+    /// Returns whether an entry had to be evicted to make room.
+    fn insert(&mut self, key: String, value: V) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+
+        self.remove(&key);
+
+        let evicted = self.entries.len() >= self.capacity;
+        if evicted {
+            if let Some((_, oldest)) = self.order.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                at: Instant::now(),
+                tick: self.tick,
+            },
+        );
+
+        evicted
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.order.remove(&entry.tick);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Lru;
+
+    #[test]
+    fn it_evicts_least_recently_used() {
+        let mut lru = Lru::new(2, Duration::from_secs(60));
+
+        assert!(!lru.insert("a".into(), 1));
+        assert!(!lru.insert("b".into(), 2));
+        assert_eq!(lru.get("a"), Some(&1));
+        assert!(lru.insert("c".into(), 3));
+
+        assert_eq!(lru.get("b"), None);
+        assert_eq!(lru.get("a"), Some(&1));
+        assert_eq!(lru.get("c"), Some(&3));
+
+        let mut lru = Lru::new(2, Duration::ZERO);
+        lru.insert("a".into(), 1);
+        assert_eq!(lru.get("a"), None);
+        assert_eq!(lru.len(), 0);
+    }
+}
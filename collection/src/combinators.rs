@@ -0,0 +1,171 @@
+//! Fetchers built out of other fetchers, see the provided methods on [`Fetcher`].
+
+use std::future::Future;
+use std::marker::PhantomData;
+
+use futures_util::future::{self, FutureExt, TryFutureExt};
+
+use crate::explain::Query;
+use crate::Fetcher;
+
+/// See [`Fetcher::map`].
+pub struct Map<A, F, Doc>(
+    pub(crate) A,
+    pub(crate) F,
+    pub(crate) PhantomData<fn() -> Doc>,
+);
+
+impl<Doc, I, A, F, T> Fetcher<Doc, I> for Map<A, F, Doc>
+where
+    A: Fetcher<Doc, I>,
+    F: FnOnce(A::Output) -> T + Send,
+{
+    type Output = T;
+    type Error = A::Error;
+
+    fn fetch(self, surface: &I) -> impl Future<Output = Result<T, A::Error>> + Send {
+        self.0.fetch(surface).map_ok(self.1)
+    }
+
+    fn query(&self) -> Option<Query> {
+        self.0.query()
+    }
+}
+
+/// See [`Fetcher::and_then`].
+pub struct AndThen<A, F, Doc>(
+    pub(crate) A,
+    pub(crate) F,
+    pub(crate) PhantomData<fn() -> Doc>,
+);
+
+impl<Doc, I, A, F, B> Fetcher<Doc, I> for AndThen<A, F, Doc>
+where
+    I: Sync,
+    A: Fetcher<Doc, I>,
+    B: Fetcher<Doc, I, Error = A::Error>,
+    F: FnOnce(A::Output) -> B + Send,
+{
+    type Output = B::Output;
+    type Error = A::Error;
+
+    fn fetch(self, surface: &I) -> impl Future<Output = Result<B::Output, A::Error>> + Send {
+        let next = self.1;
+        self.0
+            .fetch(surface)
+            .and_then(move |out| next(out).fetch(surface))
+    }
+}
+
+/// See [`Fetcher::join`].
+pub struct Join<A, B, Doc>(
+    pub(crate) A,
+    pub(crate) B,
+    pub(crate) PhantomData<fn() -> Doc>,
+);
+
+impl<Doc, I, A, B> Fetcher<Doc, I> for Join<A, B, Doc>
+where
+    A: Fetcher<Doc, I>,
+    B: Fetcher<Doc, I, Error = A::Error>,
+    A::Output: Send,
+    B::Output: Send,
+{
+    type Output = (A::Output, B::Output);
+    type Error = A::Error;
+
+    fn fetch(self, surface: &I) -> impl Future<Output = Result<Self::Output, A::Error>> + Send {
+        future::try_join(self.0.fetch(surface), self.1.fetch(surface))
+    }
+}
+
+/// See [`Fetcher::or_else`].
+pub struct OrElse<A, F, Doc>(
+    pub(crate) A,
+    pub(crate) F,
+    pub(crate) PhantomData<fn() -> Doc>,
+);
+
+impl<Doc, I, A, F, B> Fetcher<Doc, I> for OrElse<A, F, Doc>
+where
+    I: Sync,
+    A: Fetcher<Doc, I>,
+    B: Fetcher<Doc, I, Output = A::Output>,
+    F: FnOnce(A::Error) -> B + Send,
+{
+    type Output = A::Output;
+    type Error = B::Error;
+
+    fn fetch(self, surface: &I) -> impl Future<Output = Result<A::Output, B::Error>> + Send {
+        let fallback = self.1;
+        self.0
+            .fetch(surface)
+            .or_else(move |err| fallback(err).fetch(surface))
+    }
+}
+
+/// See [`Fetcher::optional`].
+pub struct Optional<A, P, Doc>(
+    pub(crate) A,
+    pub(crate) P,
+    pub(crate) PhantomData<fn() -> Doc>,
+);
+
+impl<Doc, I, A, P> Fetcher<Doc, I> for Optional<A, P, Doc>
+where
+    A: Fetcher<Doc, I>,
+    P: FnOnce(&A::Error) -> bool + Send,
+{
+    type Output = Option<A::Output>;
+    type Error = A::Error;
+
+    fn fetch(self, surface: &I) -> impl Future<Output = Result<Self::Output, A::Error>> + Send {
+        let missing = self.1;
+        self.0.fetch(surface).map(|res| match res {
+            Ok(out) => Ok(Some(out)),
+            Err(e) if missing(&e) => Ok(None),
+            Err(e) => Err(e),
+        })
+    }
+
+    fn query(&self) -> Option<Query> {
+        self.0.query()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use futures_util::FutureExt;
+
+    use crate::Fetcher;
+
+    struct Nth(usize);
+
+    impl Fetcher<(), Vec<u32>> for Nth {
+        type Output = u32;
+        type Error = usize;
+
+        fn fetch(self, surface: &Vec<u32>) -> impl Future<Output = Result<u32, usize>> + Send {
+            let res = surface.get(self.0).copied().ok_or(self.0);
+            async move { res }
+        }
+    }
+
+    fn run<F: Fetcher<(), Vec<u32>>>(f: F) -> Result<F::Output, F::Error> {
+        f.fetch(&vec![10, 20, 30]).now_or_never().unwrap()
+    }
+
+    #[test]
+    fn it_composes_fetchers() {
+        assert_eq!(run(Nth(0).map(|n| n * 2)), Ok(20));
+        assert_eq!(run(Nth(0).and_then(|n| Nth(n as usize / 10))), Ok(20));
+        assert_eq!(run(Nth(1).join(Nth(2))), Ok((20, 30)));
+        assert_eq!(run(Nth(5).or_else(|_| Nth(2))), Ok(30));
+        assert_eq!(run(Nth(5).optional(|_| true)), Ok(None));
+        assert_eq!(run(Nth(5).optional(|&n| n > 9)), Err(5));
+        assert_eq!(run(Nth(1).optional(|_| true)), Ok(Some(20)));
+        assert_eq!(run(Nth(5).join(Nth(0))), Err(5));
+    }
+}
@@ -0,0 +1,115 @@
+//! Optimistic concurrency for documents with a `#[coll(version)]` field.
+//!
+//! Replacing such a document only succeeds while the stored version is still the one it was read
+//! at, and every write through the ORM bumps the version, so a stale write fails with
+//! [`WriteError::ConcurrentModification`] instead of overwriting someone else's.
+//!
+//! [`WriteError::ConcurrentModification`]: crate::error::WriteError::ConcurrentModification
+
+use mongodb::bson::{self, doc, Bson};
+
+use crate::error::WriteError;
+use crate::Document;
+
+/// The filter matching the stored document at the version `raw` was read at.
+pub(crate) fn filter<D: Document>(raw: &bson::Document) -> bson::Document {
+    let mut filter = doc! { "_id": raw.get("_id").cloned().unwrap_or(Bson::Null) };
+    if let Some(field) = D::VERSION_FIELD {
+        filter.insert(field, raw.get(field).cloned().unwrap_or(Bson::Null));
+    }
+    filter
+}
+
+/// Moves a document about to be written on to its next version. Documents stored without one
+/// start at 1.
+pub(crate) fn bump<D: Document>(raw: &mut bson::Document) {
+    let Some(field) = D::VERSION_FIELD else {
+        return;
+    };
+
+    let next = match raw.get(field) {
+        Some(Bson::Int32(v)) => *v as i64 + 1,
+        Some(Bson::Int64(v)) => v + 1,
+        _ => 1,
+    };
+    raw.insert(field, next);
+}
+
+/// Adds the version bump to an update.
+pub(crate) fn inc<D: Document>(update: &mut bson::Document) {
+    let Some(field) = D::VERSION_FIELD else {
+        return;
+    };
+
+    match update.get_document_mut("$inc") {
+        Ok(inc) => {
+            inc.insert(field, 1i64);
+        }
+        Err(_) => {
+            update.insert("$inc", doc! { field: 1i64 });
+        }
+    }
+}
+
+/// Tells whether a write by `filter` found its document, failing if it only missed because the
+/// document moved on to another version.
+pub(crate) async fn check<D: Document>(
+    coll: &mongodb::Collection<bson::Document>,
+    mut filter: bson::Document,
+    matched: u64,
+) -> Result<bool, WriteError<D::Index>> {
+    let Some(field) = D::VERSION_FIELD.filter(|_| matched == 0) else {
+        return Ok(matched > 0);
+    };
+
+    filter.remove(field);
+    if coll.count_documents(filter.clone(), None).await? == 0 {
+        return Ok(false);
+    }
+
+    Err(WriteError::ConcurrentModification {
+        id: filter.remove("_id").unwrap_or(Bson::Null),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::{bump, filter, inc};
+    use crate::mongo::ReadOnly;
+    use crate::Document;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Account {}
+
+    impl Document for Account {
+        type Collection = ReadOnly<Self>;
+        type Id = crate::id::ObjectIdStrategy;
+        type Index = std::convert::Infallible;
+
+        const VERSION_FIELD: Option<&'static str> = Some("rev");
+    }
+
+    #[test]
+    fn it_bumps_versions() {
+        let mut raw = doc! { "_id": 1, "rev": 4 };
+        assert_eq!(filter::<Account>(&raw), doc! { "_id": 1, "rev": 4 });
+
+        bump::<Account>(&mut raw);
+        assert_eq!(raw, doc! { "_id": 1, "rev": 5i64 });
+
+        let mut fresh = doc! { "_id": 2 };
+        assert_eq!(filter::<Account>(&fresh), doc! { "_id": 2, "rev": null });
+        bump::<Account>(&mut fresh);
+        assert_eq!(fresh.get_i64("rev"), Ok(1));
+
+        let mut update = doc! { "$set": { "a": 1 } };
+        inc::<Account>(&mut update);
+        assert_eq!(update, doc! { "$set": { "a": 1 }, "$inc": { "rev": 1i64 } });
+
+        let mut update = doc! { "$inc": { "n": 1 } };
+        inc::<Account>(&mut update);
+        assert_eq!(update, doc! { "$inc": { "n": 1, "rev": 1i64 } });
+    }
+}
@@ -0,0 +1,250 @@
+//! The actor and transaction ORM writes are made in.
+//!
+//! ```ignore
+//! let session = Arc::new(Mutex::new(client.start_session(None).await?));
+//! session.lock().await.start_transaction(None).await?;
+//!
+//! Context::default()
+//!     .actor("alice")
+//!     .session(session.clone())
+//!     .scope(async { users.replace(&user).await })
+//!     .await?;
+//!
+//! session.lock().await.commit_transaction().await?;
+//! ```
+//!
+//! The context applies to the future it scopes, not to tasks it spawns. With a session, the ORM's
+//! writes and their history entries are made in it; fetchers still read outside of it.
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll};
+
+use futures_util::lock::Mutex;
+use mongodb::bson::{self, Bson};
+use mongodb::options::{
+    FindOneAndReplaceOptions, FindOneAndUpdateOptions, ReplaceOptions, UpdateOptions,
+};
+use mongodb::results::{DeleteResult, UpdateResult};
+use mongodb::ClientSession;
+
+thread_local! {
+    static CURRENT: RefCell<Context> = RefCell::default();
+}
+
+#[derive(Clone, Default)]
+pub struct Context {
+    /// Who makes the writes, recorded in the history.
+    pub actor: Option<Bson>,
+    pub session: Option<Arc<Mutex<ClientSession>>>,
+}
+
+impl Context {
+    pub fn actor(mut self, actor: impl Into<Bson>) -> Self {
+        self.actor = Some(actor.into());
+        self
+    }
+
+    pub fn session(mut self, session: Arc<Mutex<ClientSession>>) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// Runs `fut` in this context.
+    pub fn scope<F: Future>(self, fut: F) -> InContext<F> {
+        InContext {
+            context: self,
+            fut: Box::pin(fut),
+        }
+    }
+
+    /// The context of the running future, the default one outside of [`scope`](Self::scope).
+    pub fn current() -> Context {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+}
+
+pub struct InContext<F> {
+    context: Context,
+    fut: Pin<Box<F>>,
+}
+
+/// Restores the outer context once the inner future is polled, even if it panics.
+struct Restore(Option<Context>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        if let Some(outer) = self.0.take() {
+            CURRENT.with(|current| *current.borrow_mut() = outer);
+        }
+    }
+}
+
+impl<F: Future> Future for InContext<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<F::Output> {
+        let outer = CURRENT.with(|current| current.replace(self.context.clone()));
+        let _restore = Restore(Some(outer));
+        self.fut.as_mut().poll(cx)
+    }
+}
+
+type Raw = mongodb::Collection<bson::Document>;
+
+pub(crate) async fn insert_one(coll: &Raw, doc: &bson::Document) -> mongodb::error::Result<()> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            coll.insert_one_with_session(doc, None, session).await?;
+        }
+        None => {
+            coll.insert_one(doc, None).await?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn insert_many(coll: &Raw, docs: &[bson::Document]) -> mongodb::error::Result<()> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            coll.insert_many_with_session(docs, None, session).await?;
+        }
+        None => {
+            coll.insert_many(docs, None).await?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn find_one(
+    coll: &Raw,
+    filter: bson::Document,
+) -> mongodb::error::Result<Option<bson::Document>> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            coll.find_one_with_session(filter, None, session).await
+        }
+        None => coll.find_one(filter, None).await,
+    }
+}
+
+pub(crate) async fn replace_one(
+    coll: &Raw,
+    filter: bson::Document,
+    doc: &bson::Document,
+    opts: ReplaceOptions,
+) -> mongodb::error::Result<UpdateResult> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            coll.replace_one_with_session(filter, doc, opts, session)
+                .await
+        }
+        None => coll.replace_one(filter, doc, opts).await,
+    }
+}
+
+pub(crate) async fn update_one(
+    coll: &Raw,
+    filter: bson::Document,
+    update: bson::Document,
+    opts: UpdateOptions,
+) -> mongodb::error::Result<UpdateResult> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            coll.update_one_with_session(filter, update, opts, session)
+                .await
+        }
+        None => coll.update_one(filter, update, opts).await,
+    }
+}
+
+pub(crate) async fn find_one_and_update(
+    coll: &Raw,
+    filter: bson::Document,
+    update: bson::Document,
+    opts: FindOneAndUpdateOptions,
+) -> mongodb::error::Result<Option<bson::Document>> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            coll.find_one_and_update_with_session(filter, update, opts, session)
+                .await
+        }
+        None => coll.find_one_and_update(filter, update, opts).await,
+    }
+}
+
+pub(crate) async fn find_one_and_replace(
+    coll: &Raw,
+    filter: bson::Document,
+    doc: &bson::Document,
+    opts: FindOneAndReplaceOptions,
+) -> mongodb::error::Result<Option<bson::Document>> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            coll.find_one_and_replace_with_session(filter, doc, opts, session)
+                .await
+        }
+        None => coll.find_one_and_replace(filter, doc, opts).await,
+    }
+}
+
+pub(crate) async fn delete_one(
+    coll: &Raw,
+    filter: bson::Document,
+) -> mongodb::error::Result<DeleteResult> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            coll.delete_one_with_session(filter, None, session).await
+        }
+        None => coll.delete_one(filter, None).await,
+    }
+}
+
+pub(crate) async fn run_command(
+    db: &mongodb::Database,
+    cmd: bson::Document,
+) -> mongodb::error::Result<bson::Document> {
+    match Context::current().session {
+        Some(session) => {
+            let session = &mut *session.lock().await;
+            db.run_command_with_session(cmd, None, session).await
+        }
+        None => db.run_command(cmd, None).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use mongodb::bson::Bson;
+
+    use super::Context;
+
+    #[test]
+    fn it_scopes_contexts() {
+        let actor = || Context::current().actor;
+
+        let run = Context::default().actor("alice").scope(async move {
+            let inner = Context::default()
+                .actor("bob")
+                .scope(async move { actor() });
+            (actor(), inner.await, actor())
+        });
+        let (outer, inner, after) = run.now_or_never().unwrap();
+
+        assert_eq!(outer, Some(Bson::from("alice")));
+        assert_eq!(inner, Some(Bson::from("bob")));
+        assert_eq!(after, Some(Bson::from("alice")));
+        assert_eq!(actor(), None);
+    }
+}
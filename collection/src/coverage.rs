@@ -0,0 +1,470 @@
+//! Whether the declared indexes of a document serve a typed filter and sort, found without a
+//! server, for unit tests.
+//!
+//! ```ignore
+//! let f = UserColl::FIELDS;
+//! assert_indexed!(f.email.eq("a@b.c"));
+//! assert_indexed!(f.tag.eq("x"), f.name.asc()); // by the compound index tag_name
+//!
+//! // Unindexed([(Email, NoPrefix { field: "email" }), ...])
+//! let coverage = coverage::analyze(&f.age.gt(18), &Sort::default());
+//! ```
+//!
+//! The rules follow the query planner, simplified: an index serves a filter bounding its first
+//! key, and a sort following its keys after those matched by equality, in either direction.
+//! Hashed keys only match by equality, text and geospatial keys can't be matched by value, sparse
+//! indexes need the filter to exclude documents missing their key and partial indexes a filter
+//! implying their expression. Queries are analyzed in the active scope of soft deletes, which is
+//! how fetchers run them.
+
+use std::fmt;
+
+use mongodb::bson::Bson;
+
+use crate::filter::{Condition, Filter, Op, Sort};
+use crate::index::Index;
+use crate::Document;
+
+/// Why a declared index can't serve a query.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reason {
+    /// The filter doesn't bound the first key of the index.
+    NoPrefix { field: String },
+    /// The key can't be matched by the condition on it, like a range on a hashed key.
+    KeyType { field: String },
+    /// The sort on `field` doesn't follow the keys of the index.
+    Sort { field: String },
+    /// The filter may match documents without `field`, which the sparse index leaves out.
+    Sparse { field: String },
+    /// The filter doesn't imply the partial filter expression of the index.
+    Partial,
+    /// The index is hidden from the query planner.
+    Hidden,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::NoPrefix { field } => {
+                write!(f, "the filter doesn't bound its first key {}", field)
+            }
+            Reason::KeyType { field } => write!(f, "{} can't be matched by its condition", field),
+            Reason::Sort { field } => write!(f, "the sort on {} doesn't follow its keys", field),
+            Reason::Sparse { field } => {
+                write!(f, "the filter may match documents without {}", field)
+            }
+            Reason::Partial => write!(f, "the filter doesn't imply its partial filter"),
+            Reason::Hidden => write!(f, "it is hidden"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Coverage<I> {
+    /// The filter matches `_id` by value, which is always indexed.
+    Id,
+    Index(I),
+    /// Why each declared index can't serve the query.
+    Unindexed(Vec<(I, Reason)>),
+}
+
+impl<I: Index> Coverage<I> {
+    pub fn is_indexed(&self) -> bool {
+        !matches!(self, Coverage::Unindexed(_))
+    }
+
+    /// The declared index serving the query.
+    pub fn index(&self) -> Option<I> {
+        match self {
+            Coverage::Index(index) => Some(*index),
+            _ => None,
+        }
+    }
+}
+
+impl<I: Index> fmt::Display for Coverage<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Coverage::Id => write!(f, "served by the _id index"),
+            Coverage::Index(index) => write!(f, "served by the index {}", index.name()),
+            Coverage::Unindexed(misses) if misses.is_empty() => {
+                write!(f, "not served by an index, none are declared")
+            }
+            Coverage::Unindexed(misses) => {
+                write!(f, "not served by an index")?;
+                for (index, reason) in misses {
+                    write!(f, "; {}: {}", index.name(), reason)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How tightly the conditions of a filter bound a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Bound {
+    None,
+    Range,
+    In,
+    Eq,
+}
+
+fn bound(conditions: &[Condition], path: &str) -> Bound {
+    conditions
+        .iter()
+        .filter(|c| c.path == path)
+        .map(|c| match c.op {
+            Op::Eq(_) => Bound::Eq,
+            Op::In(_) => Bound::In,
+            Op::Gt(_) | Op::Gte(_) | Op::Lt(_) | Op::Lte(_) | Op::Exists(true) => Bound::Range,
+            Op::Ne(_) | Op::Exists(false) => Bound::None,
+        })
+        .max()
+        .unwrap_or(Bound::None)
+}
+
+/// Whether the conditions on `path` let documents without it through.
+fn may_be_missing(conditions: &[Condition], path: &str) -> bool {
+    !conditions
+        .iter()
+        .filter(|c| c.path == path)
+        .any(|c| match &c.op {
+            Op::Eq(v) => *v != Bson::Null,
+            Op::In(vs) => !vs.contains(&Bson::Null),
+            Op::Gt(_) | Op::Gte(_) | Op::Lt(_) | Op::Lte(_) | Op::Exists(true) => true,
+            Op::Ne(v) => *v == Bson::Null,
+            Op::Exists(false) => false,
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Asc,
+    Desc,
+    Hashed,
+    /// Text and geospatial keys.
+    Special,
+}
+
+impl Key {
+    fn of(value: &Bson) -> Self {
+        match value {
+            Bson::String(ty) if ty == "hashed" => Key::Hashed,
+            Bson::String(_) => Key::Special,
+            Bson::Int32(v) if *v < 0 => Key::Desc,
+            Bson::Int64(v) if *v < 0 => Key::Desc,
+            Bson::Double(v) if *v < 0.0 => Key::Desc,
+            _ => Key::Asc,
+        }
+    }
+}
+
+/// Whether the index keys give the order of `sort`, returning the first sort key they don't.
+fn follows_sort<'a>(
+    keys: &[(String, Key)],
+    conditions: &[Condition],
+    sort: &'a [(String, i32)],
+) -> Result<(), &'a str> {
+    let fixed = |path: &str| bound(conditions, path) == Bound::Eq;
+
+    let mut keys = keys.iter();
+    let mut direction = None;
+    for (path, dir) in sort.iter().filter(|(path, _)| !fixed(path)) {
+        // Keys matched by equality have a single value, so they can be skipped.
+        let key = keys.find(|(key, _)| key == path || !fixed(key));
+        let key_dir = match key {
+            Some((key, Key::Asc)) if key == path => 1,
+            Some((key, Key::Desc)) if key == path => -1,
+            _ => return Err(path),
+        };
+        if *direction.get_or_insert(dir * key_dir) != dir * key_dir {
+            return Err(path);
+        }
+    }
+    Ok(())
+}
+
+/// How many keys of `index` bound the query, or why it can't serve it.
+fn serves<I: Index>(
+    index: I,
+    conditions: &[Condition],
+    filtered: bool,
+    sort: &[(String, i32)],
+) -> Result<usize, Reason> {
+    let model = index.model();
+    let keys = model
+        .keys
+        .iter()
+        .map(|(path, value)| (path.clone(), Key::of(value)))
+        .collect::<Vec<_>>();
+    let options = model.options.unwrap_or_default();
+    if options.hidden == Some(true) {
+        return Err(Reason::Hidden);
+    }
+    let Some((first, first_key)) = keys.first() else {
+        return Err(Reason::NoPrefix {
+            field: String::new(),
+        });
+    };
+
+    let partial = options.partial_filter_expression.unwrap_or_default();
+    let implied = partial.iter().all(|(path, value)| {
+        conditions
+            .iter()
+            .any(|c| c.path == *path && c.op == Op::Eq(value.clone()))
+    });
+    if !implied {
+        return Err(Reason::Partial);
+    }
+
+    let mut bounded = 0;
+    for (path, key) in &keys {
+        let bound = bound(conditions, path);
+        let usable = match key {
+            Key::Asc | Key::Desc => bound > Bound::None,
+            Key::Hashed => bound >= Bound::In,
+            Key::Special => false,
+        };
+        if !usable {
+            break;
+        }
+        bounded += 1;
+        if bound == Bound::Range {
+            break;
+        }
+    }
+
+    if bounded == 0 {
+        let field = first.clone();
+        match first_key {
+            Key::Special => return Err(Reason::KeyType { field }),
+            Key::Hashed if bound(conditions, first) == Bound::Range => {
+                return Err(Reason::KeyType { field })
+            }
+            // Without a filter, an index can still be walked for its order.
+            _ if filtered || sort.is_empty() => return Err(Reason::NoPrefix { field }),
+            _ => {}
+        }
+    }
+
+    if options.sparse == Some(true) && may_be_missing(conditions, first) {
+        return Err(Reason::Sparse {
+            field: first.clone(),
+        });
+    }
+
+    follows_sort(&keys, conditions, sort).map_err(|field| Reason::Sort {
+        field: field.to_owned(),
+    })?;
+    Ok(bounded)
+}
+
+fn coverage<I: Index>(
+    conditions: &[Condition],
+    filtered: bool,
+    sort: &[(String, i32)],
+) -> Coverage<I> {
+    let by_id = conditions
+        .iter()
+        .any(|c| c.path == "_id" && matches!(c.op, Op::Eq(_) | Op::In(_)));
+    if by_id {
+        return Coverage::Id;
+    }
+
+    let mut best = None;
+    let mut misses = Vec::new();
+    for &index in I::ALL {
+        match serves(index, conditions, filtered, sort) {
+            Ok(bounded) if best.is_none_or(|(_, most)| bounded > most) => {
+                best = Some((index, bounded))
+            }
+            Ok(_) => {}
+            Err(reason) => misses.push((index, reason)),
+        }
+    }
+
+    match best {
+        Some((index, _)) => Coverage::Index(index),
+        None => Coverage::Unindexed(misses),
+    }
+}
+
+/// Finds the declared index of `D` serving `filter` and `sort`, preferring the one bounding the
+/// most keys. A filter with a value which could not be encoded has no conditions.
+pub fn analyze<D: Document>(filter: &Filter<D>, sort: &Sort<D>) -> Coverage<D::Index> {
+    let mut conditions = filter.conditions().to_vec();
+    let filtered = !conditions.is_empty();
+    if let Some(field) = D::SOFT_DELETE.filter(|field| conditions.iter().all(|c| c.path != *field))
+    {
+        conditions.push(Condition {
+            path: field.to_owned(),
+            op: Op::Eq(Bson::Null),
+        });
+    }
+
+    coverage(&conditions, filtered, sort.keys())
+}
+
+/// Asserts that a declared index serves a typed filter, and optionally a sort, see
+/// [`coverage`](crate::coverage).
+///
+/// ```ignore
+/// assert_indexed!(UserColl::FIELDS.email.eq("a@b.c"));
+/// assert_indexed!(UserColl::FIELDS.tag.eq("x"), UserColl::FIELDS.name.desc());
+/// ```
+#[macro_export]
+macro_rules! assert_indexed {
+    ($filter:expr $(,)?) => {
+        $crate::assert_indexed!($filter, $crate::filter::Sort::default())
+    };
+    ($filter:expr, $sort:expr $(,)?) => {{
+        let filter = $filter;
+        let coverage = $crate::coverage::analyze(&filter, &$sort);
+        assert!(coverage.is_indexed(), "{:?} is {}", filter, coverage);
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{doc, Bson};
+    use mongodb::IndexModel;
+
+    use super::{coverage, Coverage, Reason};
+    use crate::filter::{Condition, Field, Filter, Op, Sort};
+    use crate::index::{self, Index};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum TestIndex {
+        Email,
+        TagName,
+        Hashed,
+        Active,
+        Hidden,
+    }
+
+    impl Index for TestIndex {
+        const ALL: &'static [Self] = &[
+            Self::Email,
+            Self::TagName,
+            Self::Hashed,
+            Self::Active,
+            Self::Hidden,
+        ];
+
+        fn name(self) -> &'static str {
+            match self {
+                Self::Email => "email",
+                Self::TagName => "tag_name",
+                Self::Hashed => "hashed",
+                Self::Active => "active",
+                Self::Hidden => "hidden",
+            }
+        }
+
+        fn from_name(_: &str) -> Option<Self> {
+            None
+        }
+
+        fn model(self) -> IndexModel {
+            let (keys, sparse, partial) = match self {
+                Self::Email => (doc! { "email": 1 }, true, None),
+                Self::TagName => (doc! { "tag": 1, "name": -1 }, false, None),
+                Self::Hashed => (doc! { "h": "hashed" }, false, None),
+                Self::Active => (doc! { "at": 1 }, false, Some(doc! { "deleted": null })),
+                Self::Hidden => (doc! { "x": 1 }, false, None),
+            };
+            let hidden = self == Self::Hidden;
+            index::model(self.name(), keys, false, sparse, hidden, None, partial)
+        }
+    }
+
+    struct Doc;
+
+    fn analyze(filter: Filter<Doc>, sort: Sort<Doc>) -> Coverage<TestIndex> {
+        let conditions = filter.conditions().to_vec();
+        coverage(&conditions, !conditions.is_empty(), sort.keys())
+    }
+
+    #[test]
+    fn it_finds_serving_indexes() {
+        let field = |path| Field::<Doc, String>::new(path);
+        let none = Sort::default;
+        let deleted = Condition {
+            path: "deleted".into(),
+            op: Op::Eq(Bson::Null),
+        };
+
+        assert_eq!(analyze(field("_id").eq("x"), none()), Coverage::Id);
+        assert_eq!(
+            analyze(field("email").eq("a"), none()),
+            Coverage::Index(TestIndex::Email)
+        );
+        assert_eq!(
+            analyze(field("tag").eq("x"), field("name").asc()),
+            Coverage::Index(TestIndex::TagName)
+        );
+        assert_eq!(
+            analyze(
+                Filter::default(),
+                field("tag").desc().then(field("name").asc())
+            ),
+            Coverage::Index(TestIndex::TagName)
+        );
+        assert_eq!(
+            analyze(field("h").is_in(["a", "b"]), none()),
+            Coverage::Index(TestIndex::Hashed)
+        );
+
+        let Coverage::Unindexed(misses) =
+            analyze(Field::<Doc, Option<String>>::new("email").eq(None), none())
+        else {
+            panic!("a sparse index can't find missing fields");
+        };
+        assert_eq!(
+            misses[0],
+            (
+                TestIndex::Email,
+                Reason::Sparse {
+                    field: "email".into()
+                }
+            )
+        );
+
+        let Coverage::Unindexed(misses) = analyze(
+            field("tag").gt("x"),
+            field("tag").asc().then(field("name").asc()),
+        ) else {
+            panic!("the sort goes against the index");
+        };
+        assert_eq!(
+            misses[1],
+            (
+                TestIndex::TagName,
+                Reason::Sort {
+                    field: "name".into()
+                }
+            )
+        );
+
+        let Coverage::Unindexed(misses) =
+            analyze(field("h").gt("a").and(field("at").gt("a")), none())
+        else {
+            panic!("hashed keys only match by equality");
+        };
+        assert_eq!(misses[2].1, Reason::KeyType { field: "h".into() });
+        assert_eq!(misses[3].1, Reason::Partial);
+
+        let Coverage::Unindexed(misses) = analyze(field("x").eq("a"), none()) else {
+            panic!("hidden indexes aren't used");
+        };
+        assert_eq!(misses[4], (TestIndex::Hidden, Reason::Hidden));
+
+        let mut conditions = field("at").gt("a").conditions().to_vec();
+        conditions.push(deleted);
+        assert_eq!(
+            coverage::<TestIndex>(&conditions, true, &[]),
+            Coverage::Index(TestIndex::Active)
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use mongodb::error::{ErrorKind, WriteFailure};
+
+use crate::hooks::Hook;
+use crate::index::Index;
+use crate::migrate::{BoxError, MigrationError};
+use crate::validate::Violations;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Mongo(#[from] mongodb::error::Error),
+    #[error(transparent)]
+    Serialize(#[from] mongodb::bson::ser::Error),
+    #[error(transparent)]
+    Deserialize(#[from] mongodb::bson::de::Error),
+    #[error(transparent)]
+    Migration(#[from] MigrationError),
+    /// The document broke its validation rules.
+    #[error("invalid document: {0}")]
+    Invalid(#[from] Violations),
+    /// A hook of the document failed, aborting the operation.
+    #[error("{hook:?} hook failed: {source}")]
+    Hook { hook: Hook, source: BoxError },
+    /// A field of a tracked document can't be updated through its path, see
+    /// [`tracking`](crate::tracking).
+    #[error("field {0:?} can not be updated by path")]
+    Unaddressable(String),
+    /// A batched load failed; every caller in the batch sees the same error.
+    #[error(transparent)]
+    Batch(Arc<Error>),
+}
+
+/// Errors of writes through the ORM, `I` being the generated index enum of the document.
+#[derive(Debug, Error)]
+pub enum WriteError<I> {
+    /// The write would have duplicated `key` in the unique `index`.
+    #[error("duplicate key {key} in index {index:?}")]
+    DuplicateKey { index: I, key: String },
+    /// The document was written by someone else since it was read, see
+    /// [`concurrency`](crate::concurrency).
+    #[error("document {id} was modified concurrently")]
+    ConcurrentModification { id: mongodb::bson::Bson },
+    #[error(transparent)]
+    Other(#[from] Error),
+}
+
+impl<I: Index> From<mongodb::error::Error> for WriteError<I> {
+    fn from(e: mongodb::error::Error) -> Self {
+        let message = match e.kind.as_ref() {
+            ErrorKind::Write(WriteFailure::WriteError(we)) if we.code == 11000 => {
+                Some(we.message.as_str())
+            }
+            ErrorKind::BulkWrite(failure) => failure
+                .write_errors
+                .iter()
+                .flatten()
+                .find(|we| we.code == 11000)
+                .map(|we| we.message.as_str()),
+            ErrorKind::Command(c) if c.code == 11000 => Some(c.message.as_str()),
+            _ => None,
+        };
+
+        match message.and_then(duplicate_key::<I>) {
+            Some((index, key)) => Self::DuplicateKey { index, key },
+            None => Self::Other(e.into()),
+        }
+    }
+}
+
+impl<I> From<mongodb::bson::ser::Error> for WriteError<I> {
+    fn from(e: mongodb::bson::ser::Error) -> Self {
+        Self::Other(e.into())
+    }
+}
+
+impl<I> From<mongodb::bson::de::Error> for WriteError<I> {
+    fn from(e: mongodb::bson::de::Error) -> Self {
+        Self::Other(e.into())
+    }
+}
+
+/// A key which a write would have duplicated in the unique `index`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuplicateKey<I> {
+    pub index: I,
+    pub key: String,
+}
+
+/// Picks the index and key out of a message like
+/// `E11000 duplicate key error collection: db.users index: email dup key: { email: "a" }`.
+pub(crate) fn duplicate_key<I: Index>(message: &str) -> Option<(I, String)> {
+    let (_, rest) = message.split_once(" index: ")?;
+    let (name, key) = rest.split_once(" dup key: ")?;
+
+    Some((I::from_name(name)?, key.to_owned()))
+}
+
+#[derive(Debug, Error)]
+pub enum IndexCreationError {
+    #[error(transparent)]
+    Mongo(#[from] mongodb::error::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::IndexModel;
+
+    use super::duplicate_key;
+    use crate::index::Index;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum TestIndex {
+        Email,
+    }
+
+    impl Index for TestIndex {
+        const ALL: &'static [Self] = &[Self::Email];
+
+        fn name(self) -> &'static str {
+            "email"
+        }
+
+        fn from_name(name: &str) -> Option<Self> {
+            matches!(name, "email" | "email_1").then_some(Self::Email)
+        }
+
+        fn model(self) -> IndexModel {
+            IndexModel::default()
+        }
+    }
+
+    #[test]
+    fn it_parses_duplicate_keys() {
+        let message = r#"E11000 duplicate key error collection: app.users index: email_1 dup key: { email: "a@b.c" }"#;
+
+        assert_eq!(
+            duplicate_key(message),
+            Some((TestIndex::Email, r#"{ email: "a@b.c" }"#.to_owned()))
+        );
+        assert_eq!(
+            duplicate_key::<TestIndex>("E11000 index: _id_ dup key: { _id: 1 }"),
+            None
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Query plans of filter-based fetchers, and a check for fetches scanning whole collections.
+//!
+//! ```ignore
+//! let plan = users.explain(UserColl::by_email("a@b.c"), Verbosity::ExecutionStats).await?;
+//! assert_eq!(plan.unwrap().index.as_deref(), Some("email"));
+//!
+//! // in tests
+//! explain::set_scan_check(ScanCheck::Panic);
+//! ```
+//!
+//! Fetchers expose their query through [`Fetcher::query`]; lookups by `_id` don't, as they
+//! always use its index. With a scan check set, every fetch with a query from a collection with
+//! declared indexes is explained first, which costs a round trip.
+//!
+//! [`Fetcher::query`]: crate::Fetcher::query
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use mongodb::bson::{self, doc, Bson};
+
+use crate::index::Index;
+use crate::soft_delete::Scope;
+use crate::{Document, Error, Fetcher};
+
+/// What a fetcher runs, see [`Fetcher::query`](crate::Fetcher::query).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    pub filter: bson::Document,
+    pub limit: Option<i64>,
+    pub scope: Scope,
+}
+
+impl Query {
+    pub fn new(filter: bson::Document) -> Self {
+        Self {
+            filter,
+            limit: None,
+            scope: Scope::Active,
+        }
+    }
+
+    /// The `find` command of the query, as the fetcher runs it on `D`.
+    pub fn command<D: Document>(&self, coll: &str) -> bson::Document {
+        let mut filter = self.filter.clone();
+        self.scope.apply::<D>(&mut filter);
+
+        let mut find = doc! { "find": coll, "filter": filter };
+        if let Some(limit) = self.limit {
+            find.insert("limit", limit);
+        }
+        find
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    #[default]
+    QueryPlanner,
+    ExecutionStats,
+    AllPlansExecution,
+}
+
+impl Verbosity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Verbosity::QueryPlanner => "queryPlanner",
+            Verbosity::ExecutionStats => "executionStats",
+            Verbosity::AllPlansExecution => "allPlansExecution",
+        }
+    }
+}
+
+/// The winning plan of a query. The counts are only known with [`Verbosity::ExecutionStats`] or
+/// more.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    /// The stages of the plan, from the root down, going through every input of stages with
+    /// several, e.g. `OR`, in turn.
+    pub stages: Vec<String>,
+    /// The first index scanned.
+    pub index: Option<String>,
+    pub docs_examined: Option<i64>,
+    pub keys_examined: Option<i64>,
+    pub returned: Option<i64>,
+    /// The whole output of `explain`.
+    pub raw: bson::Document,
+}
+
+fn int(doc: &bson::Document, key: &str) -> Option<i64> {
+    match doc.get(key)? {
+        Bson::Int32(v) => Some(*v as i64),
+        Bson::Int64(v) => Some(*v),
+        Bson::Double(v) => Some(*v as i64),
+        _ => None,
+    }
+}
+
+impl Plan {
+    pub fn from_explain(raw: bson::Document) -> Self {
+        let mut stages = Vec::new();
+        let mut index = None;
+
+        let winning = raw
+            .get_document("queryPlanner")
+            .and_then(|planner| planner.get_document("winningPlan"))
+            .ok();
+        // Plans run by the slot based engine nest the classic plan in `queryPlan`.
+        let mut pending =
+            Vec::from_iter(winning.map(|plan| plan.get_document("queryPlan").unwrap_or(plan)));
+        while let Some(stage) = pending.pop() {
+            if let Ok(name) = stage.get_str("stage") {
+                stages.push(name.to_owned());
+            }
+            if index.is_none() {
+                index = stage.get_str("indexName").ok().map(str::to_owned);
+            }
+
+            if let Ok(input) = stage.get_document("inputStage") {
+                pending.push(input);
+            }
+            if let Ok(inputs) = stage.get_array("inputStages") {
+                pending.extend(inputs.iter().rev().filter_map(Bson::as_document));
+            }
+        }
+
+        let stats = raw.get_document("executionStats").ok();
+        Self {
+            stages,
+            index,
+            docs_examined: stats.and_then(|s| int(s, "totalDocsExamined")),
+            keys_examined: stats.and_then(|s| int(s, "totalKeysExamined")),
+            returned: stats.and_then(|s| int(s, "nReturned")),
+            raw,
+        }
+    }
+
+    /// The stage at the root of the plan.
+    pub fn stage(&self) -> Option<&str> {
+        self.stages.first().map(String::as_str)
+    }
+
+    pub fn is_collscan(&self) -> bool {
+        self.stages.iter().any(|stage| stage == "COLLSCAN")
+    }
+}
+
+/// Explains `query` as run on the collection `coll` of `D`.
+pub async fn run<D: Document>(
+    db: &mongodb::Database,
+    coll: &str,
+    query: &Query,
+    verbosity: Verbosity,
+) -> Result<Plan, Error> {
+    let command = doc! { "explain": query.command::<D>(coll), "verbosity": verbosity.as_str() };
+    let raw = db.run_command(command, None).await?;
+    Ok(Plan::from_explain(raw))
+}
+
+/// What happens when a fetch scans a whole collection which has declared indexes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanCheck {
+    Off,
+    /// Prints the query to stderr.
+    Log,
+    /// Panics, for tests. Fetches whose query can't be explained panic too.
+    Panic,
+}
+
+static SCAN_CHECK: AtomicU8 = AtomicU8::new(0);
+
+/// Sets the scan check of the whole process, off by default. The setting is shared by every
+/// thread, so tests running in parallel all see the last one set; set it once, e.g. in a shared
+/// setup, rather than per test.
+pub fn set_scan_check(check: ScanCheck) {
+    SCAN_CHECK.store(check as u8, Ordering::Relaxed);
+}
+
+pub fn scan_check() -> ScanCheck {
+    match SCAN_CHECK.load(Ordering::Relaxed) {
+        1 => ScanCheck::Log,
+        2 => ScanCheck::Panic,
+        _ => ScanCheck::Off,
+    }
+}
+
+/// The scan check of a fetch about to run `f`, used by the collections' `fetch`.
+pub fn check<'a, D, I, F>(
+    db: &'a mongodb::Database,
+    coll: &'a str,
+    f: &F,
+) -> impl Future<Output = ()> + Send + 'a
+where
+    D: Document,
+    F: Fetcher<D, I>,
+{
+    let mode = scan_check();
+    let query = (mode != ScanCheck::Off && !<D::Index as Index>::ALL.is_empty())
+        .then(|| f.query())
+        .flatten();
+
+    async move {
+        let Some(query) = query else {
+            return;
+        };
+
+        let plan = match run::<D>(db, coll, &query, Verbosity::QueryPlanner).await {
+            Ok(plan) => plan,
+            Err(e) => {
+                let message = format!("scan check of a fetch from {} failed: {}", coll, e);
+                match mode {
+                    ScanCheck::Panic => panic!("{}", message),
+                    _ => eprintln!("{}", message),
+                }
+                return;
+            }
+        };
+
+        if plan.is_collscan() {
+            let message = format!(
+                "fetch from {} scans the whole collection: {}",
+                coll,
+                query.command::<D>(coll)
+            );
+            match mode {
+                ScanCheck::Panic => panic!("{}", message),
+                _ => eprintln!("{}", message),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::{Plan, Query};
+    use crate::mongo::ReadOnly;
+    use crate::Document;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Post {}
+
+    impl Document for Post {
+        type Collection = ReadOnly<Self>;
+        type Id = crate::id::ObjectIdStrategy;
+        type Index = std::convert::Infallible;
+
+        const SOFT_DELETE: Option<&'static str> = Some("deleted");
+    }
+
+    #[test]
+    fn it_summarizes_plans() {
+        let plan = Plan::from_explain(doc! {
+            "queryPlanner": { "winningPlan": {
+                "stage": "FETCH",
+                "inputStage": { "stage": "IXSCAN", "indexName": "email" },
+            } },
+            "executionStats": { "nReturned": 1, "totalKeysExamined": 1, "totalDocsExamined": 1 },
+        });
+        assert_eq!(plan.stages, ["FETCH", "IXSCAN"]);
+        assert_eq!(plan.stage(), Some("FETCH"));
+        assert_eq!(plan.index.as_deref(), Some("email"));
+        assert_eq!(plan.docs_examined, Some(1));
+        assert!(!plan.is_collscan());
+
+        let plan = Plan::from_explain(doc! {
+            "queryPlanner": { "winningPlan": { "queryPlan": { "stage": "COLLSCAN" } } },
+        });
+        assert!(plan.is_collscan() && plan.returned.is_none());
+
+        let plan = Plan::from_explain(doc! {
+            "queryPlanner": { "winningPlan": {
+                "stage": "OR",
+                "inputStages": [
+                    { "stage": "FETCH", "inputStage": { "stage": "IXSCAN", "indexName": "a" } },
+                    { "stage": "COLLSCAN" },
+                ],
+            } },
+        });
+        assert_eq!(plan.stages, ["OR", "FETCH", "IXSCAN", "COLLSCAN"]);
+        assert_eq!(plan.index.as_deref(), Some("a"));
+        assert!(plan.is_collscan());
+
+        let query = Query {
+            limit: Some(1),
+            ..Query::new(doc! { "a": 1 })
+        };
+        assert_eq!(
+            query.command::<Post>("docs"),
+            doc! { "find": "docs", "filter": { "a": 1, "deleted": null }, "limit": 1_i64 }
+        );
+    }
+}
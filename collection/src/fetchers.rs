@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use mongodb::bson::{self, doc, Bson};
+use serde::de::DeserializeOwned;
+
+use crate::explain::Query;
+use crate::hooks::{self, Hook};
+use crate::id::{self, IntoId};
+use crate::mongo::Source;
+use crate::soft_delete::{Scope, ScopedFetcher};
+use crate::{migrate, Document, Error, Fetcher};
+
+/// Decodes a fetched document and runs its `after_load` hook.
+pub(crate) async fn load<D>(raw: bson::Document) -> Result<D, Error>
+where
+    D: Document + DeserializeOwned + Send,
+{
+    let mut doc = migrate::decode(raw)?;
+    hooks::call(&mut doc, Hook::AfterLoad).await?;
+    Ok(doc)
+}
+
+pub struct ById<K>(pub K);
+
+impl<D, K, S> Fetcher<D, S> for ById<K>
+where
+    D: Document + DeserializeOwned + Send + Sync,
+    S: Source<D>,
+    K: IntoId<D> + Send,
+{
+    type Output = Option<D>;
+    type Error = Error;
+
+    async fn fetch(self, surface: &S) -> Result<Self::Output, Self::Error> {
+        self.fetch_in(surface, Scope::Active).await
+    }
+}
+
+impl<D, K> ScopedFetcher<D> for ById<K>
+where
+    D: Document + DeserializeOwned + Send + Sync,
+    K: IntoId<D> + Send,
+{
+    type Output = Option<D>;
+
+    async fn fetch_in<S: Source<D>>(self, surface: &S, scope: Scope) -> Result<Option<D>, Error> {
+        FindOne(doc! { "_id": self.0.into() })
+            .fetch_in(surface, scope)
+            .await
+    }
+}
+
+pub struct FindOne(pub bson::Document);
+
+impl<D, S> Fetcher<D, S> for FindOne
+where
+    D: Document + DeserializeOwned + Send + Sync,
+    S: Source<D>,
+{
+    type Output = Option<D>;
+    type Error = Error;
+
+    async fn fetch(self, surface: &S) -> Result<Self::Output, Self::Error> {
+        self.fetch_in(surface, Scope::Active).await
+    }
+
+    fn query(&self) -> Option<Query> {
+        Some(Query {
+            limit: Some(1),
+            ..Query::new(self.0.clone())
+        })
+    }
+}
+
+impl<D> ScopedFetcher<D> for FindOne
+where
+    D: Document + DeserializeOwned + Send + Sync,
+{
+    type Output = Option<D>;
+
+    async fn fetch_in<S: Source<D>>(self, surface: &S, scope: Scope) -> Result<Option<D>, Error> {
+        let mut filter = self.0;
+        scope.apply::<D>(&mut filter);
+
+        let raw = surface.reader();
+        match raw.find_one(filter, None).await? {
+            Some(doc) => Ok(Some(load(doc).await?)),
+            None => Ok(None),
+        }
+    }
+}
+
+pub struct Find(pub bson::Document);
+
+impl<D, S> Fetcher<D, S> for Find
+where
+    D: Document + DeserializeOwned + Send + Sync,
+    S: Source<D>,
+{
+    type Output = Vec<D>;
+    type Error = Error;
+
+    async fn fetch(self, surface: &S) -> Result<Self::Output, Self::Error> {
+        self.fetch_in(surface, Scope::Active).await
+    }
+
+    fn query(&self) -> Option<Query> {
+        Some(Query::new(self.0.clone()))
+    }
+}
+
+impl<D> ScopedFetcher<D> for Find
+where
+    D: Document + DeserializeOwned + Send + Sync,
+{
+    type Output = Vec<D>;
+
+    async fn fetch_in<S: Source<D>>(self, surface: &S, scope: Scope) -> Result<Vec<D>, Error> {
+        let mut filter = self.0;
+        scope.apply::<D>(&mut filter);
+
+        let raw = surface.reader();
+        let mut cursor = raw.find(filter, None).await?;
+
+        let mut out = Vec::new();
+        while cursor.advance().await? {
+            out.push(load(cursor.deserialize_current()?).await?);
+        }
+        Ok(out)
+    }
+}
+
+/// Like [`Find`], streaming the documents instead of collecting them.
+pub struct FindStream(pub bson::Document);
+
+pub type DocumentStream<D> = BoxStream<'static, Result<D, Error>>;
+
+impl<D, S> Fetcher<D, S> for FindStream
+where
+    D: Document + DeserializeOwned + Send + Sync + 'static,
+    S: Source<D>,
+{
+    type Output = DocumentStream<D>;
+    type Error = Error;
+
+    async fn fetch(self, surface: &S) -> Result<Self::Output, Self::Error> {
+        self.fetch_in(surface, Scope::Active).await
+    }
+
+    fn query(&self) -> Option<Query> {
+        Some(Query::new(self.0.clone()))
+    }
+}
+
+impl<D> ScopedFetcher<D> for FindStream
+where
+    D: Document + DeserializeOwned + Send + Sync + 'static,
+{
+    type Output = DocumentStream<D>;
+
+    async fn fetch_in<S: Source<D>>(
+        self,
+        surface: &S,
+        scope: Scope,
+    ) -> Result<DocumentStream<D>, Error> {
+        let mut filter = self.0;
+        scope.apply::<D>(&mut filter);
+
+        let raw = surface.reader();
+        let cursor = raw.find(filter, None).await?;
+        Ok(cursor.map_err(Error::from).and_then(load).boxed())
+    }
+}
+
+/// Fetches documents by `_id` with a single `$in` query. The output follows the order of the
+/// ids, with `None` for those not found; ids are expected to be distinct.
+pub struct ByIds<K>(pub Vec<K>);
+
+impl<D, K, S> Fetcher<D, S> for ByIds<K>
+where
+    D: Document + DeserializeOwned + Send + Sync,
+    S: Source<D>,
+    K: IntoId<D> + Send,
+{
+    type Output = Vec<Option<D>>;
+    type Error = Error;
+
+    async fn fetch(self, surface: &S) -> Result<Self::Output, Self::Error> {
+        self.fetch_in(surface, Scope::Active).await
+    }
+}
+
+impl<D, K> ScopedFetcher<D> for ByIds<K>
+where
+    D: Document + DeserializeOwned + Send + Sync,
+    K: IntoId<D> + Send,
+{
+    type Output = Vec<Option<D>>;
+
+    async fn fetch_in<S: Source<D>>(
+        self,
+        surface: &S,
+        scope: Scope,
+    ) -> Result<Vec<Option<D>>, Error> {
+        let ids = self.0.into_iter().map(Into::into).collect::<Vec<Bson>>();
+        let mut filter = doc! { "_id": { "$in": ids.clone() } };
+        scope.apply::<D>(&mut filter);
+
+        let raw = surface.reader();
+        let mut cursor = raw.find(filter, None).await?;
+
+        let mut found = HashMap::new();
+        while cursor.advance().await? {
+            let doc: bson::Document = cursor.deserialize_current()?;
+            let key = id::key(doc.get("_id").unwrap_or(&Bson::Null));
+            found.insert(key, doc);
+        }
+
+        let mut docs = Vec::with_capacity(ids.len());
+        for raw in in_order(&ids, &found) {
+            docs.push(match raw {
+                Some(raw) => Some(load(raw.clone()).await?),
+                None => None,
+            });
+        }
+        Ok(docs)
+    }
+}
+
+/// The documents found by the key of their `_id`, in the order of `ids`.
+fn in_order<'a>(
+    ids: &[Bson],
+    found: &'a HashMap<String, bson::Document>,
+) -> Vec<Option<&'a bson::Document>> {
+    ids.iter().map(|id| found.get(&id::key(id))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mongodb::bson::{doc, Bson};
+
+    use super::in_order;
+    use crate::id;
+
+    #[test]
+    fn it_orders_found_documents_by_id() {
+        let found = [doc! { "_id": 1_i64 }, doc! { "_id": "a" }]
+            .into_iter()
+            .map(|doc| (id::key(doc.get("_id").unwrap()), doc))
+            .collect::<HashMap<_, _>>();
+
+        let ids = [
+            Bson::String("a".into()),
+            Bson::Int32(1),
+            Bson::Int32(2),
+            Bson::Double(1.0),
+        ];
+        let docs = in_order(&ids, &found);
+
+        assert_eq!(docs[0], Some(&doc! { "_id": "a" }));
+        assert_eq!(docs[1], Some(&doc! { "_id": 1_i64 }));
+        assert_eq!(docs[2], None);
+        assert_eq!(docs[3], docs[1]);
+        assert_ne!(id::key(&Bson::Double(1.5)), id::key(&Bson::Int32(1)));
+        assert_ne!(id::key(&Bson::String("1".into())), id::key(&Bson::Int32(1)));
+    }
+}
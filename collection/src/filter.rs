@@ -0,0 +1,336 @@
+//! Filters and sorts built from the typed field paths generated for every document.
+//!
+//! ```ignore
+//! let f = UserColl::FIELDS;
+//! let filter = f.email.eq("a@b.c").and(f.age.gte(18));
+//! let sort = f.name.asc().then(f.age.desc());
+//! users.fetch(FindStream(filter.document()?)).await?;
+//! ```
+//!
+//! Values are checked against the type of the field, and encoded as the field would be. Paths
+//! into nested documents are built with [`Field::field`].
+
+use std::borrow::Cow;
+use std::marker::PhantomData;
+
+use mongodb::bson::{self, Bson};
+use serde::Serialize;
+
+use crate::Error;
+
+/// The path of a field of type `T` in the document `D`.
+pub struct Field<D, T> {
+    path: Cow<'static, str>,
+    _marker: PhantomData<fn() -> (D, T)>,
+}
+
+impl<D, T> Clone for Field<D, T> {
+    fn clone(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<D, T> std::fmt::Debug for Field<D, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Field").field(&self.path).finish()
+    }
+}
+
+impl<D, T> Field<D, T> {
+    /// A field by its serialized name, used by the derive.
+    pub const fn new(path: &'static str) -> Self {
+        Self {
+            path: Cow::Borrowed(path),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The field `name` of the document nested in this field.
+    pub fn field<U>(&self, name: &str) -> Field<D, U> {
+        Field {
+            path: Cow::Owned(format!("{}.{}", self.path, name)),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn exists(&self, exists: bool) -> Filter<D> {
+        self.filter(Ok(Op::Exists(exists)))
+    }
+
+    pub fn asc(&self) -> Sort<D> {
+        self.sort(1)
+    }
+
+    pub fn desc(&self) -> Sort<D> {
+        self.sort(-1)
+    }
+
+    fn filter(&self, op: Result<Op, bson::ser::Error>) -> Filter<D> {
+        let conditions = op.map(|op| {
+            vec![Condition {
+                path: self.path.to_string(),
+                op,
+            }]
+        });
+        Filter {
+            conditions,
+            _marker: PhantomData,
+        }
+    }
+
+    fn sort(&self, direction: i32) -> Sort<D> {
+        Sort {
+            keys: vec![(self.path.to_string(), direction)],
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<D, T: Serialize> Field<D, T> {
+    pub fn eq(&self, value: impl Into<T>) -> Filter<D> {
+        self.filter(bson::to_bson(&value.into()).map(Op::Eq))
+    }
+
+    pub fn ne(&self, value: impl Into<T>) -> Filter<D> {
+        self.filter(bson::to_bson(&value.into()).map(Op::Ne))
+    }
+
+    pub fn gt(&self, value: impl Into<T>) -> Filter<D> {
+        self.filter(bson::to_bson(&value.into()).map(Op::Gt))
+    }
+
+    pub fn gte(&self, value: impl Into<T>) -> Filter<D> {
+        self.filter(bson::to_bson(&value.into()).map(Op::Gte))
+    }
+
+    pub fn lt(&self, value: impl Into<T>) -> Filter<D> {
+        self.filter(bson::to_bson(&value.into()).map(Op::Lt))
+    }
+
+    pub fn lte(&self, value: impl Into<T>) -> Filter<D> {
+        self.filter(bson::to_bson(&value.into()).map(Op::Lte))
+    }
+
+    pub fn is_in<V: Into<T>>(&self, values: impl IntoIterator<Item = V>) -> Filter<D> {
+        let values: Result<Vec<_>, _> = values
+            .into_iter()
+            .map(|value| bson::to_bson(&value.into()))
+            .collect();
+        self.filter(values.map(Op::In))
+    }
+}
+
+/// The operator of a [`Condition`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Eq(Bson),
+    Ne(Bson),
+    Gt(Bson),
+    Gte(Bson),
+    Lt(Bson),
+    Lte(Bson),
+    In(Vec<Bson>),
+    Exists(bool),
+}
+
+impl Op {
+    fn operator(&self) -> (&'static str, Bson) {
+        match self {
+            Op::Eq(v) => ("$eq", v.clone()),
+            Op::Ne(v) => ("$ne", v.clone()),
+            Op::Gt(v) => ("$gt", v.clone()),
+            Op::Gte(v) => ("$gte", v.clone()),
+            Op::Lt(v) => ("$lt", v.clone()),
+            Op::Lte(v) => ("$lte", v.clone()),
+            Op::In(vs) => ("$in", Bson::Array(vs.clone())),
+            Op::Exists(b) => ("$exists", Bson::Boolean(*b)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub path: String,
+    pub op: Op,
+}
+
+/// Conditions which all have to hold, failing if a value could not be encoded.
+pub struct Filter<D> {
+    conditions: Result<Vec<Condition>, bson::ser::Error>,
+    _marker: PhantomData<fn() -> D>,
+}
+
+impl<D> std::fmt::Debug for Filter<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Filter").field(&self.conditions).finish()
+    }
+}
+
+impl<D> Default for Filter<D> {
+    fn default() -> Self {
+        Self {
+            conditions: Ok(Vec::new()),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<D> Filter<D> {
+    pub fn and(self, other: Filter<D>) -> Self {
+        let conditions = match (self.conditions, other.conditions) {
+            (Ok(mut a), Ok(b)) => {
+                a.extend(b);
+                Ok(a)
+            }
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+        Self {
+            conditions,
+            _marker: PhantomData,
+        }
+    }
+
+    /// The conditions of the filter, empty if a value could not be encoded.
+    pub fn conditions(&self) -> &[Condition] {
+        self.conditions.as_deref().unwrap_or_default()
+    }
+
+    /// The filter document. Conditions on the same path are merged into one set of operators;
+    /// an operator repeated on a path goes to an `$and`, so every condition still holds.
+    pub fn document(self) -> Result<bson::Document, Error> {
+        let conditions = self.conditions?;
+
+        let mut filter = bson::Document::new();
+        let mut and = Vec::new();
+        for Condition { path, op } in &conditions {
+            let single = conditions.iter().filter(|c| &c.path == path).count() == 1;
+            match op {
+                Op::Eq(value) if single => {
+                    filter.insert(path, value.clone());
+                }
+                _ => {
+                    let (operator, value) = op.operator();
+                    let ops = filter
+                        .entry(path.clone())
+                        .or_insert_with(|| Bson::Document(bson::Document::new()));
+                    if let Bson::Document(ops) = ops {
+                        if ops.contains_key(operator) {
+                            let mut repeated = bson::Document::new();
+                            repeated.insert(operator, value);
+                            let mut condition = bson::Document::new();
+                            condition.insert(path, repeated);
+                            and.push(Bson::Document(condition));
+                        } else {
+                            ops.insert(operator, value);
+                        }
+                    }
+                }
+            }
+        }
+        if !and.is_empty() {
+            filter.insert("$and", and);
+        }
+        Ok(filter)
+    }
+}
+
+/// The keys of a sort, in order, with their direction.
+pub struct Sort<D> {
+    keys: Vec<(String, i32)>,
+    _marker: PhantomData<fn() -> D>,
+}
+
+impl<D> Clone for Sort<D> {
+    fn clone(&self) -> Self {
+        Self {
+            keys: self.keys.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<D> std::fmt::Debug for Sort<D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Sort").field(&self.keys).finish()
+    }
+}
+
+impl<D> Default for Sort<D> {
+    fn default() -> Self {
+        Self {
+            keys: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<D> Sort<D> {
+    pub fn then(mut self, other: Sort<D>) -> Self {
+        self.keys.extend(other.keys);
+        self
+    }
+
+    /// The paths of the sort with `1` or `-1`.
+    pub fn keys(&self) -> &[(String, i32)] {
+        &self.keys
+    }
+
+    pub fn document(&self) -> bson::Document {
+        self.keys
+            .iter()
+            .map(|(path, direction)| (path.clone(), Bson::Int32(*direction)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::Field;
+
+    struct Doc;
+
+    #[test]
+    fn it_builds_filters() {
+        let name = Field::<Doc, String>::new("name");
+        let age = Field::<Doc, Option<u64>>::new("age");
+        let city = Field::<Doc, ()>::new("address").field::<String>("city");
+
+        let filter = name
+            .eq("a")
+            .and(age.gte(18))
+            .and(age.lt(65))
+            .and(city.is_in(["x", "y"]));
+        assert_eq!(
+            filter.document().unwrap(),
+            doc! {
+                "name": "a",
+                "age": { "$gte": 18_i64, "$lt": 65_i64 },
+                "address.city": { "$in": ["x", "y"] },
+            }
+        );
+        assert_eq!(
+            name.asc().then(age.desc()).document(),
+            doc! { "name": 1, "age": -1 }
+        );
+
+        assert!(age.eq(Some(u64::MAX)).and(name.eq("a")).document().is_err());
+
+        assert_eq!(
+            age.gt(5).and(age.gt(1)).document().unwrap(),
+            doc! { "age": { "$gt": 5_i64 }, "$and": [{ "age": { "$gt": 1_i64 } }] }
+        );
+        assert_eq!(
+            name.eq("a").and(name.eq("b")).document().unwrap(),
+            doc! { "name": { "$eq": "a" }, "$and": [{ "name": { "$eq": "b" } }] }
+        );
+    }
+}
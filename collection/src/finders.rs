@@ -0,0 +1,78 @@
+//! The fetchers behind the `by_` finders generated for every declared index.
+//!
+//! ```ignore
+//! #[coll(index(single email, unique))]       // UserColl::by_email(&str) -> Option<User>
+//! #[coll(index(compound tag_name))]          // UserColl::by_tag_name(&str, &str) -> stream of User
+//!                                            // UserColl::by_name(&str), for the prefix (name)
+//! ```
+//!
+//! Unique indexes find one document, the others stream every match. Compound indexes also get a
+//! finder for each of their prefixes, named after its fields, unless another finder has that
+//! name. Text and geospatial keys can't be queried by value, so finders stop before them.
+
+use mongodb::bson::{self, Bson};
+use serde::de::DeserializeOwned;
+
+use crate::explain::Query;
+use crate::mongo::Source;
+use crate::soft_delete::{Scope, ScopedFetcher};
+use crate::{Document, Error, Fetcher};
+
+/// The filter matching `fields` by value.
+pub fn filter<const N: usize>(
+    fields: [(&str, Result<Bson, bson::ser::Error>); N],
+) -> Result<bson::Document, Error> {
+    let mut filter = bson::Document::new();
+    for (field, value) in fields {
+        filter.insert(field, value?);
+    }
+    Ok(filter)
+}
+
+/// A finder, failing if its values could not be encoded.
+pub struct Finder<F>(pub Result<F, Error>);
+
+impl<D, F, S> Fetcher<D, S> for Finder<F>
+where
+    D: Document + DeserializeOwned + Send + Sync,
+    S: Source<D>,
+    F: Fetcher<D, S, Error = Error> + Send,
+{
+    type Output = F::Output;
+    type Error = Error;
+
+    async fn fetch(self, surface: &S) -> Result<F::Output, Error> {
+        self.0?.fetch(surface).await
+    }
+
+    fn query(&self) -> Option<Query> {
+        self.0.as_ref().ok()?.query()
+    }
+}
+
+impl<D, F> ScopedFetcher<D> for Finder<F>
+where
+    D: Document + DeserializeOwned + Send + Sync,
+    F: ScopedFetcher<D> + Send,
+{
+    type Output = F::Output;
+
+    async fn fetch_in<S: Source<D>>(self, surface: &S, scope: Scope) -> Result<F::Output, Error> {
+        self.0?.fetch_in(surface, scope).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{self, doc};
+
+    use super::filter;
+
+    #[test]
+    fn it_builds_filters() {
+        let filter = filter([("name", bson::to_bson("a")), ("tag", bson::to_bson(&2))]);
+        assert_eq!(filter.unwrap(), doc! { "name": "a", "tag": 2 });
+
+        assert!(super::filter([("n", bson::to_bson(&u64::MAX))]).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! The change history of documents declaring `#[coll(option(history))]`.
+//!
+//! Every ORM write of such a document appends an [`Entry`] to the `<name>_history` collection,
+//! holding the document before and after the change and the actor of the current [`Context`].
+//! With a session in the context, the entry is written in its transaction. Such documents can't
+//! be written in [`Bulk`](crate::bulk::Bulk), which would go unrecorded.
+//!
+//! [`Context`]: crate::context::Context
+
+use std::future::Future;
+use std::marker::PhantomData;
+
+use mongodb::bson::{self, doc, oid::ObjectId, Bson, DateTime};
+use mongodb::options::{FindOneOptions, FindOptions, IndexOptions};
+use mongodb::IndexModel;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::context::{self, Context};
+use crate::id::IntoId;
+use crate::{migrate, Collection, Document, Error, Fetcher};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Insert,
+    Replace,
+    Update,
+    Delete,
+    Restore,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// The `_id` of the changed document.
+    pub doc_id: Bson,
+    pub op: Op,
+    pub at: DateTime,
+    pub actor: Option<Bson>,
+    /// The stored document before the change, `None` when inserted.
+    pub before: Option<bson::Document>,
+    /// The stored document after the change, `None` when deleted.
+    pub after: Option<bson::Document>,
+}
+
+pub fn collection_name(name: &str) -> String {
+    format!("{}_history", name)
+}
+
+/// The history of the documents `D`, fetched from with [`AsOf`] and [`Changes`].
+pub struct History<D>(pub mongodb::Collection<Entry>, PhantomData<fn() -> D>);
+
+impl<D> Clone for History<D> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone(), PhantomData)
+    }
+}
+
+impl<D> History<D> {
+    /// The history of the collection `name`.
+    pub fn new(db: &mongodb::Database, name: &str) -> Self {
+        Self(db.collection(&collection_name(name)), PhantomData)
+    }
+
+    pub(crate) fn index() -> IndexModel {
+        let mut opts = IndexOptions::default();
+        opts.name = Some("doc_id_at".to_owned());
+
+        IndexModel::builder()
+            .keys(doc! { "doc_id": 1, "at": -1 })
+            .options(opts)
+            .build()
+    }
+
+    /// Appends the entry of a change to the document `before` or `after`, in the current context.
+    pub(crate) async fn record(
+        &self,
+        op: Op,
+        before: Option<bson::Document>,
+        after: Option<bson::Document>,
+    ) -> Result<(), Error> {
+        let doc_id = before
+            .as_ref()
+            .or(after.as_ref())
+            .and_then(|doc| doc.get("_id"))
+            .cloned()
+            .unwrap_or(Bson::Null);
+
+        let entry = Entry {
+            id: ObjectId::new(),
+            doc_id,
+            op,
+            at: DateTime::now(),
+            actor: Context::current().actor,
+            before,
+            after,
+        };
+
+        let raw = self.0.clone_with_type();
+        context::insert_one(&raw, &bson::to_document(&entry)?).await?;
+        Ok(())
+    }
+}
+
+impl<D: Document> Collection for History<D> {
+    type Internal = mongodb::Collection<Entry>;
+    type Document = D;
+
+    fn fetch<F: Fetcher<Self::Document, Self::Internal>>(
+        &self,
+        f: F,
+    ) -> impl Future<Output = Result<F::Output, F::Error>> + Send {
+        f.fetch(&self.0)
+    }
+}
+
+/// Reconstructs the document as it was at the given time, `None` if it did not exist then.
+pub struct AsOf<K>(pub K, pub DateTime);
+
+impl<D, K> Fetcher<D, mongodb::Collection<Entry>> for AsOf<K>
+where
+    D: Document + DeserializeOwned,
+    K: IntoId<D> + Send,
+{
+    type Output = Option<D>;
+    type Error = Error;
+
+    async fn fetch(self, surface: &mongodb::Collection<Entry>) -> Result<Option<D>, Error> {
+        let mut opts = FindOneOptions::default();
+        opts.sort = Some(doc! { "at": -1, "_id": -1 });
+
+        let filter = doc! { "doc_id": self.0.into(), "at": { "$lte": self.1 } };
+        match surface.find_one(filter, opts).await? {
+            Some(Entry {
+                after: Some(after), ..
+            }) => Ok(Some(migrate::decode(after)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Every change of the document, oldest first.
+pub struct Changes<K>(pub K);
+
+impl<D, K> Fetcher<D, mongodb::Collection<Entry>> for Changes<K>
+where
+    K: IntoId<D> + Send,
+{
+    type Output = Vec<Entry>;
+    type Error = Error;
+
+    async fn fetch(self, surface: &mongodb::Collection<Entry>) -> Result<Vec<Entry>, Error> {
+        let mut opts = FindOptions::default();
+        opts.sort = Some(doc! { "at": 1, "_id": 1 });
+
+        let mut cursor = surface.find(doc! { "doc_id": self.0.into() }, opts).await?;
+
+        let mut out = Vec::new();
+        while cursor.advance().await? {
+            out.push(cursor.deserialize_current()?);
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{self, doc, oid::ObjectId, DateTime};
+
+    use super::{collection_name, Entry, Op};
+
+    #[test]
+    fn it_encodes_entries() {
+        let entry = Entry {
+            id: ObjectId::new(),
+            doc_id: 1.into(),
+            op: Op::Delete,
+            at: DateTime::from_millis(0),
+            actor: None,
+            before: Some(doc! { "_id": 1 }),
+            after: None,
+        };
+
+        let raw = bson::to_document(&entry).unwrap();
+        assert_eq!(raw.get_str("op").unwrap(), "delete");
+        assert_eq!(bson::from_document::<Entry>(raw).unwrap(), entry);
+        assert_eq!(collection_name("users"), "users_history");
+    }
+}
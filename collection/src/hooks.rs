@@ -0,0 +1,191 @@
+//! Lifecycle hooks of documents declaring `#[coll(hooks)]`.
+//!
+//! The ORM's inserts, replacements, upserts, saves and deletes run the hooks, as do the standard
+//! fetchers on every document they load. Updates by operators, i.e. `update` and `update_doc`,
+//! and bulk writes bypass them, as there is no document to run them on. A hook returning an error
+//! aborts the operation, or fails the fetch for `after_load`.
+//!
+//! [`Cached`](crate::cache::Cached) hits are clones of the document as it was loaded, so
+//! `after_load` isn't run again for them; it should not depend on when the document is loaded.
+
+use std::future::Future;
+
+use mongodb::bson;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::migrate::{self, BoxError};
+use crate::{Document, Error};
+
+pub type HookError = BoxError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    BeforeInsert,
+    AfterInsert,
+    BeforeUpdate,
+    AfterDelete,
+    AfterLoad,
+}
+
+/// Implemented by documents declaring `#[coll(hooks)]`. Changes made by the `before_` hooks are
+/// written; `before_update` runs when the whole document is written, by a replace or upsert, and
+/// before a save diffs the document.
+pub trait DocumentHooks: Send + Sync {
+    fn before_insert(&mut self) -> impl Future<Output = Result<(), HookError>> + Send {
+        async { Ok(()) }
+    }
+
+    fn after_insert(&self) -> impl Future<Output = Result<(), HookError>> + Send {
+        async { Ok(()) }
+    }
+
+    fn before_update(&mut self) -> impl Future<Output = Result<(), HookError>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Runs with the document as it was before it was deleted.
+    fn after_delete(&self) -> impl Future<Output = Result<(), HookError>> + Send {
+        async { Ok(()) }
+    }
+
+    fn after_load(&mut self) -> impl Future<Output = Result<(), HookError>> + Send {
+        async { Ok(()) }
+    }
+}
+
+/// Dispatches to the hooks of `D`, used by the generated `Document` impls.
+pub async fn run<D: DocumentHooks>(doc: &mut D, hook: Hook) -> Result<(), HookError> {
+    match hook {
+        Hook::BeforeInsert => doc.before_insert().await,
+        Hook::AfterInsert => doc.after_insert().await,
+        Hook::BeforeUpdate => doc.before_update().await,
+        Hook::AfterDelete => doc.after_delete().await,
+        Hook::AfterLoad => doc.after_load().await,
+    }
+}
+
+pub(crate) async fn call<D: Document + Send>(doc: &mut D, hook: Hook) -> Result<(), Error> {
+    if !D::HOOKS {
+        return Ok(());
+    }
+
+    D::run_hook(doc, hook)
+        .await
+        .map_err(|source| Error::Hook { hook, source })
+}
+
+/// Runs `hook` on the document `raw` holds, writing back the changes of `before_` hooks.
+pub(crate) async fn call_raw<D>(raw: &mut bson::Document, hook: Hook) -> Result<(), Error>
+where
+    D: Document + Serialize + DeserializeOwned + Send,
+{
+    if !D::HOOKS {
+        return Ok(());
+    }
+
+    let mut doc: D = migrate::decode(raw.clone())?;
+    call(&mut doc, hook).await?;
+
+    if matches!(hook, Hook::BeforeInsert | Hook::BeforeUpdate) {
+        *raw = migrate::encode(&doc)?;
+    }
+    Ok(())
+}
+
+/// Checks the field rules of the document about to be written, as the `before_` hooks left it in
+/// `raw`. `doc` is checked as is when there are no hooks to change it.
+pub(crate) fn validate<D>(doc: &D, raw: &bson::Document) -> Result<(), Error>
+where
+    D: Document + DeserializeOwned,
+{
+    if D::HOOKS {
+        D::validate_fields(&migrate::decode::<D>(raw.clone())?)?;
+    } else {
+        D::validate_fields(doc)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use futures_util::FutureExt;
+    use mongodb::bson::doc;
+    use serde::{Deserialize, Serialize};
+
+    use super::{call_raw, validate, Hook, HookError};
+    use crate::mongo::ReadOnly;
+    use crate::validate::{Violation, Violations};
+    use crate::{Document, Error};
+
+    #[derive(Serialize, Deserialize)]
+    struct Account {
+        email: String,
+    }
+
+    impl Document for Account {
+        type Collection = ReadOnly<Self>;
+        type Id = crate::id::ObjectIdStrategy;
+        type Index = std::convert::Infallible;
+
+        const HOOKS: bool = true;
+
+        fn run_hook(
+            doc: &mut Self,
+            hook: Hook,
+        ) -> impl Future<Output = Result<(), HookError>> + Send {
+            super::run(doc, hook)
+        }
+
+        fn validate_fields(doc: &Self) -> Result<(), Violations> {
+            if doc.email == doc.email.to_lowercase() {
+                return Ok(());
+            }
+            Err(Violations(vec![Violation {
+                path: "email".into(),
+                rule: "lowercase",
+                message: "must be lowercase".into(),
+            }]))
+        }
+    }
+
+    impl super::DocumentHooks for Account {
+        async fn before_insert(&mut self) -> Result<(), HookError> {
+            if !self.email.contains('@') {
+                return Err("not an email".into());
+            }
+            self.email = self.email.to_lowercase();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn it_runs_hooks() {
+        let account = Account {
+            email: "A@B.C".into(),
+        };
+        let mut raw = doc! { "email": "A@B.C" };
+        assert!(matches!(validate(&account, &raw), Err(Error::Invalid(_))));
+
+        let run = call_raw::<Account>(&mut raw, Hook::BeforeInsert);
+        run.now_or_never().unwrap().unwrap();
+        assert_eq!(raw, doc! { "email": "a@b.c" });
+        validate(&account, &raw).unwrap();
+
+        let mut raw = doc! { "email": "A" };
+        let run = call_raw::<Account>(&mut raw, Hook::AfterInsert);
+        run.now_or_never().unwrap().unwrap();
+
+        let run = call_raw::<Account>(&mut raw, Hook::BeforeInsert);
+        assert!(matches!(
+            run.now_or_never().unwrap(),
+            Err(Error::Hook {
+                hook: Hook::BeforeInsert,
+                ..
+            })
+        ));
+        assert_eq!(raw, doc! { "email": "A" });
+    }
+}
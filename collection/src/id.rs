@@ -0,0 +1,315 @@
+//! Typed document ids, and the strategies generating them, chosen with `#[coll(id = ...)]`.
+//!
+//! Besides the strategies below, `#[coll(id = path::To)]` takes any [`IdStrategy`]. Ids numbered
+//! from a sequence are declared along with the sequence, and left for the insert to fill in:
+//!
+//! ```ignore
+//! #[serde(rename = "_id")]
+//! #[coll(id = Sequence, sequence = "invoice_ids")]
+//! id: Option<Id<Invoice>>,
+//! ```
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{self, Bson, Uuid};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::schema::JsonSchema;
+use crate::Document;
+
+/// How ids of a document are represented and generated.
+pub trait IdStrategy {
+    type Key: Serialize
+        + DeserializeOwned
+        + Into<Bson>
+        + Clone
+        + PartialEq
+        + Eq
+        + Hash
+        + fmt::Debug
+        + Send
+        + Sync;
+
+    /// A new id, or `None` if ids are numbered from a sequence on insert instead.
+    fn generate() -> Option<Self::Key>;
+}
+
+/// `#[coll(id)]` or `#[coll(id = ObjectId)]`, the default.
+pub struct ObjectIdStrategy;
+
+impl IdStrategy for ObjectIdStrategy {
+    type Key = ObjectId;
+
+    fn generate() -> Option<ObjectId> {
+        Some(ObjectId::new())
+    }
+}
+
+/// `#[coll(id = UuidV4)]`
+pub struct UuidV4Strategy;
+
+impl IdStrategy for UuidV4Strategy {
+    type Key = Uuid;
+
+    fn generate() -> Option<Uuid> {
+        Some(Uuid::new())
+    }
+}
+
+/// `#[coll(id = UuidV7)]`, random UUIDs which sort by creation time.
+pub struct UuidV7Strategy;
+
+impl IdStrategy for UuidV7Strategy {
+    type Key = Uuid;
+
+    fn generate() -> Option<Uuid> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+
+        Some(uuid_v7(millis, Uuid::new().bytes()))
+    }
+}
+
+/// The 48 bit timestamp leads, the rest is random but for the version and variant bits.
+fn uuid_v7(millis: u64, random: [u8; 16]) -> Uuid {
+    let mut bytes = random;
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    bytes[6] = 0x70 | (bytes[6] & 0x0f);
+    bytes[8] = 0x80 | (bytes[8] & 0x3f);
+
+    Uuid::from_bytes(bytes)
+}
+
+/// `#[coll(id = String)]`, the hex form of a new `ObjectId`.
+pub struct StringStrategy;
+
+impl IdStrategy for StringStrategy {
+    type Key = String;
+
+    fn generate() -> Option<String> {
+        Some(ObjectId::new().to_hex())
+    }
+}
+
+/// `#[coll(id = Sequence, sequence = "name")]`, numbers from the [`Sequences`] of the collection,
+/// allocated when the document is inserted.
+///
+/// [`Sequences`]: crate::sequence::Sequences
+pub struct SequenceStrategy;
+
+impl IdStrategy for SequenceStrategy {
+    type Key = i64;
+
+    fn generate() -> Option<i64> {
+        None
+    }
+}
+
+pub type KeyOf<T> = <<T as Document>::Id as IdStrategy>::Key;
+
+/// The id of a `T`, which can't be mistaken for the id of another document. Serialized exactly as
+/// the underlying key.
+pub struct Id<T: Document>(KeyOf<T>, PhantomData<fn() -> T>);
+
+impl<T: Document> Id<T> {
+    /// A new id from the strategy of `T`, `None` if it numbers ids from a sequence.
+    pub fn generate() -> Option<Self> {
+        T::Id::generate().map(Self::from_key)
+    }
+
+    pub fn from_key(key: KeyOf<T>) -> Self {
+        Self(key, PhantomData)
+    }
+
+    pub fn key(&self) -> &KeyOf<T> {
+        &self.0
+    }
+
+    pub fn into_key(self) -> KeyOf<T> {
+        self.0
+    }
+}
+
+impl<T: Document> Clone for Id<T> {
+    fn clone(&self) -> Self {
+        Self::from_key(self.0.clone())
+    }
+}
+
+impl<T: Document> PartialEq for Id<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T: Document> Eq for Id<T> {}
+
+impl<T: Document> Hash for Id<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl<T: Document> fmt::Debug for Id<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: Document> Serialize for Id<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Document> Deserialize<'de> for Id<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        KeyOf::<T>::deserialize(deserializer).map(Self::from_key)
+    }
+}
+
+impl<T: Document> From<Id<T>> for Bson {
+    fn from(id: Id<T>) -> Self {
+        id.0.into()
+    }
+}
+
+impl<T: Document> JsonSchema for Id<T>
+where
+    KeyOf<T>: JsonSchema,
+{
+    fn json_schema() -> bson::Document {
+        KeyOf::<T>::json_schema()
+    }
+}
+
+/// Values usable as the `_id` of a `D`: its [`Id`], a raw key of the type its strategy generates,
+/// or an unchecked [`Bson`].
+pub trait IntoId<D>: Into<Bson> {}
+
+impl<D: Document> IntoId<D> for Id<D> {}
+
+impl<D> IntoId<D> for Bson {}
+
+impl<D: Document> IntoId<D> for &str where D::Id: IdStrategy<Key = String> {}
+
+/// Types a `#[coll(id)]` field of a `D` may have: its [`Id`] or its raw key, optionally wrapped
+/// in an `Option` for ids generated on insert.
+pub trait IdField<D> {}
+
+impl<D: Document> IdField<D> for Id<D> {}
+
+impl<D, T: IdField<D>> IdField<D> for Option<T> {}
+
+macro_rules! raw_id {
+    ($($t:ty),*) => {
+        $(
+            impl<D: Document> IntoId<D> for $t where D::Id: IdStrategy<Key = $t> {}
+            impl<D: Document> IdField<D> for $t where D::Id: IdStrategy<Key = $t> {}
+        )*
+    };
+}
+
+raw_id!(ObjectId, Uuid, String, i32, i64);
+
+/// Fails to compile unless `F` is an [`IdField`] of `D`, called by the derive.
+#[doc(hidden)]
+pub fn assert_id_field<D, F: IdField<D>>() {}
+
+/// A key for an id, equal for ids the server matches as equal: integral numbers of any type
+/// are the same key.
+pub fn key(id: &Bson) -> String {
+    let int = match id {
+        Bson::Int32(v) => Some(i64::from(*v)),
+        Bson::Int64(v) => Some(*v),
+        Bson::Double(v) if v.fract() == 0.0 && v.abs() < i64::MAX as f64 => Some(*v as i64),
+        _ => None,
+    };
+    match int {
+        Some(v) => v.to_string(),
+        None => id.clone().into_canonical_extjson().to_string(),
+    }
+}
+
+/// Fills in the `_id` of a document about to be inserted if it has none, returning it. Ids
+/// numbered from a sequence are left for [`sequence::assign`](crate::sequence::assign), and
+/// returned as `Null`.
+pub fn ensure_id<D: Document>(raw: &mut bson::Document) -> Bson {
+    if let Some(id) = raw.get("_id").filter(|id| **id != Bson::Null) {
+        return id.clone();
+    }
+
+    let Some(id) = D::Id::generate() else {
+        return Bson::Null;
+    };
+    let id: Bson = id.into();
+    raw.insert("_id", id.clone());
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use mongodb::bson::{doc, to_bson, Bson};
+
+    use super::{ensure_id, uuid_v7, Id, SequenceStrategy};
+    use crate::mongo::ReadOnly;
+    use crate::sequence::{self, MemorySequences};
+    use crate::Document;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct User {}
+
+    impl Document for User {
+        type Collection = ReadOnly<Self>;
+        type Id = crate::id::ObjectIdStrategy;
+        type Index = std::convert::Infallible;
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Invoice {}
+
+    impl Document for Invoice {
+        type Collection = ReadOnly<Self>;
+        type Id = SequenceStrategy;
+        type Index = std::convert::Infallible;
+
+        const SEQUENCES: &'static [(&'static str, &'static str)] = &[("_id", "invoice_ids")];
+    }
+
+    #[test]
+    fn it_builds_v7_uuids() {
+        let id = uuid_v7(0x0123_4567_89ab, [0xff; 16]).bytes();
+
+        assert_eq!(id[..6], [0x01, 0x23, 0x45, 0x67, 0x89, 0xab]);
+        assert_eq!(id[6] >> 4, 7);
+        assert_eq!(id[8] >> 6, 0b10);
+        assert!(uuid_v7(1, [0xff; 16]).bytes() < uuid_v7(2, [0; 16]).bytes());
+    }
+
+    #[test]
+    fn it_serializes_transparently() {
+        let id = Id::<User>::generate().unwrap();
+
+        assert_eq!(to_bson(&id).unwrap(), Bson::ObjectId(*id.key()));
+    }
+
+    #[test]
+    fn it_numbers_ids_from_sequences() {
+        assert!(Id::<Invoice>::generate().is_none());
+
+        let mut raw = doc! { "_id": Bson::Null };
+        assert_eq!(ensure_id::<Invoice>(&mut raw), Bson::Null);
+
+        let seq = MemorySequences::default();
+        let run = sequence::assign::<Invoice, _>(&seq, vec![&mut raw]);
+        run.now_or_never().unwrap().unwrap();
+        assert_eq!(raw, doc! { "_id": 1_i64 });
+    }
+}
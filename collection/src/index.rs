@@ -0,0 +1,153 @@
+//! Indexes declared with `#[coll(index(...))]`, as the enum generated for each document.
+//!
+//! ```
+//! use collection::Document;
+//!
+//! #[derive(serde::Serialize, serde::Deserialize, Document)]
+//! #[coll(pub PlaceColl places)]
+//! pub struct Place {
+//!     #[coll(index(single name, unique))]
+//!     name: String,
+//!     #[coll(index(single location, type = Geo2DSphere))]
+//!     location: Vec<f64>,
+//! }
+//! ```
+//!
+//! Declarations MongoDB would reject when creating the indexes fail to compile instead. GeoHaystack
+//! indexes are gone since MongoDB 5.0:
+//!
+//! ```compile_fail
+//! # use collection::Document;
+//! #[derive(serde::Serialize, serde::Deserialize, Document)]
+//! #[coll(pub PlaceColl places)]
+//! pub struct Place {
+//!     #[coll(index(single location, type = GeoHaystack))]
+//!     location: Vec<f64>,
+//! }
+//! ```
+//!
+//! Hashed indexes can't be unique:
+//!
+//! ```compile_fail
+//! # use collection::Document;
+//! #[derive(serde::Serialize, serde::Deserialize, Document)]
+//! #[coll(pub UserColl users)]
+//! pub struct User {
+//!     #[coll(index(single email, unique, type = Hash))]
+//!     email: String,
+//! }
+//! ```
+//!
+//! A collection has at most one text index:
+//!
+//! ```compile_fail
+//! # use collection::Document;
+//! #[derive(serde::Serialize, serde::Deserialize, Document)]
+//! #[coll(pub PostColl posts)]
+//! pub struct Post {
+//!     #[coll(index(single title, type = Text))]
+//!     title: String,
+//!     #[coll(index(single body, type = Text))]
+//!     body: String,
+//! }
+//! ```
+//!
+//! Geospatial indexes need coordinates, which a string isn't:
+//!
+//! ```compile_fail
+//! # use collection::Document;
+//! #[derive(serde::Serialize, serde::Deserialize, Document)]
+//! #[coll(pub PlaceColl places)]
+//! pub struct Place {
+//!     #[coll(index(single address, type = Geo2DSphere))]
+//!     address: String,
+//! }
+//! ```
+//!
+//! Misspelled arguments are reported with the closest known one, here "did you mean `unique`?":
+//!
+//! ```compile_fail
+//! # use collection::Document;
+//! #[derive(serde::Serialize, serde::Deserialize, Document)]
+//! #[coll(pub UserColl users)]
+//! pub struct User {
+//!     #[coll(index(single email, uniqe))]
+//!     email: String,
+//! }
+//! ```
+//!
+//! A compound index no field joins only warns, as a use of a deprecated item:
+//!
+//! ```compile_fail
+//! #![deny(deprecated)]
+//! # use collection::Document;
+//! #[derive(serde::Serialize, serde::Deserialize, Document)]
+//! #[coll(pub UserColl users)]
+//! #[coll(index(compound name_age))]
+//! pub struct User {
+//!     name: String,
+//!     age: u32,
+//! }
+//! ```
+
+use std::convert::Infallible;
+use std::fmt::Debug;
+use std::time::Duration;
+
+use mongodb::bson::Document;
+use mongodb::options::IndexOptions;
+use mongodb::IndexModel;
+
+pub trait Index: Debug + Copy + Send + Sync + 'static {
+    const ALL: &'static [Self];
+
+    /// The name the index is created with.
+    fn name(self) -> &'static str;
+
+    /// Looks up an index by the name it has on the server, which is either the declared name or
+    /// the one MongoDB derives from the keys when none is given.
+    fn from_name(name: &str) -> Option<Self>;
+
+    fn model(self) -> IndexModel;
+}
+
+/// For documents without declared indexes.
+impl Index for Infallible {
+    const ALL: &'static [Self] = &[];
+
+    fn name(self) -> &'static str {
+        match self {}
+    }
+
+    fn from_name(_: &str) -> Option<Self> {
+        None
+    }
+
+    fn model(self) -> IndexModel {
+        match self {}
+    }
+}
+
+/// Builds the model of a declared index, used by the generated [`Index`] impls.
+pub fn model(
+    name: &str,
+    keys: Document,
+    unique: bool,
+    sparse: bool,
+    hidden: bool,
+    expire_after_seconds: Option<u64>,
+    partial_filter: Option<Document>,
+) -> IndexModel {
+    let mut opts = IndexOptions::default();
+    opts.name = Some(name.to_owned());
+    opts.unique = unique.then_some(true);
+    opts.sparse = sparse.then_some(true);
+    opts.hidden = hidden.then_some(true);
+    opts.expire_after = expire_after_seconds.map(Duration::from_secs);
+    opts.partial_filter_expression = partial_filter;
+
+    let mut model = IndexModel::default();
+    model.keys = keys;
+    model.options = Some(opts);
+    model
+}
@@ -1,29 +1,165 @@
-#![feature(async_fn_in_trait)]
+use std::future::Future;
+use std::marker::PhantomData;
+
 pub use collection_macro::*;
 
+pub mod bulk;
+pub mod cache;
+pub mod combinators;
+pub mod concurrency;
+pub mod context;
+pub mod coverage;
+pub mod error;
+pub mod explain;
+pub mod fetchers;
+pub mod filter;
+pub mod finders;
+pub mod history;
+pub mod hooks;
+pub mod id;
+pub mod index;
+pub mod loader;
+pub mod migrate;
+pub mod mongo;
+pub mod schema;
+pub mod sequence;
+pub mod soft_delete;
+pub mod timestamps;
+pub mod tracking;
+pub mod validate;
+pub mod watch;
+
+pub use error::Error;
+pub use id::Id;
+
 pub trait Document {
     type Collection: Collection<Document = Self>;
+    /// How ids are generated, see [`id`].
+    type Id: id::IdStrategy;
+    /// The declared indexes, `Infallible` if there are none.
+    type Index: index::Index;
+
+    /// Current schema version, see [`migrate`].
+    const VERSION: u32 = 0;
+
+    /// Fields numbered with `#[coll(sequence = ...)]`, by their serialized name and sequence, see
+    /// [`sequence`].
+    const SEQUENCES: &'static [(&'static str, &'static str)] = &[];
+
+    /// The field declared with `#[coll(version)]`, see [`concurrency`].
+    const VERSION_FIELD: Option<&'static str> = None;
+
+    /// Fields declared with `#[coll(created_at)]` and `#[coll(updated_at)]`, see [`timestamps`].
+    const TIMESTAMPS: timestamps::Timestamps = timestamps::Timestamps::NONE;
+
+    /// The `deleted_at` field of `#[coll(option(soft_delete = ...))]`, see [`soft_delete`].
+    const SOFT_DELETE: Option<&'static str> = None;
+
+    /// Whether the document declares `#[coll(hooks)]`, see [`hooks`].
+    const HOOKS: bool = false;
+
+    /// Whether the document declares `#[coll(option(history))]`, see [`history`].
+    const HISTORY: bool = false;
+
+    fn migrations() -> &'static migrate::Migrations {
+        static EMPTY: migrate::Migrations = migrate::Migrations::new();
+        &EMPTY
+    }
+
+    /// Checks the `#[coll(validate(...))]` rules of the document, see [`validate`].
+    fn validate_fields(_doc: &Self) -> Result<(), validate::Violations> {
+        Ok(())
+    }
+
+    /// Runs a hook of the document, forwarded to its [`hooks::DocumentHooks`] by the derive.
+    fn run_hook(
+        _doc: &mut Self,
+        _hook: hooks::Hook,
+    ) -> impl Future<Output = Result<(), hooks::HookError>> + Send {
+        async { Ok(()) }
+    }
 }
 
 pub trait Collection {
     type Internal;
     type Document: Document;
 
-    async fn fetch<F: Fetcher<Self::Document, Self::Internal>>(
+    /// The returned future is `Send` whenever the fetcher's is, which [`Fetcher`] requires.
+    fn fetch<F: Fetcher<Self::Document, Self::Internal>>(
         &self,
         f: F,
-    ) -> Result<F::Output, F::Error>;
+    ) -> impl Future<Output = Result<F::Output, F::Error>> + Send;
 }
 
 pub trait Fetcher<Doc, Internal> {
     type Output;
     type Error;
 
-    async fn fetch(self, surface: &Internal) -> Result<Self::Output, Self::Error>;
+    fn fetch(
+        self,
+        surface: &Internal,
+    ) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send;
+
+    /// The query the fetcher runs, if it runs a single one by filter, see [`explain`].
+    fn query(&self) -> Option<explain::Query> {
+        None
+    }
+
+    /// Transforms the output with `f`.
+    fn map<F>(self, f: F) -> combinators::Map<Self, F, Doc>
+    where
+        Self: Sized,
+    {
+        combinators::Map(self, f, PhantomData)
+    }
+
+    /// Runs the fetcher returned by `f` on the output, against the same collection.
+    fn and_then<F>(self, f: F) -> combinators::AndThen<Self, F, Doc>
+    where
+        Self: Sized,
+    {
+        combinators::AndThen(self, f, PhantomData)
+    }
+
+    /// Runs both fetchers concurrently, failing if either does.
+    fn join<B>(self, other: B) -> combinators::Join<Self, B, Doc>
+    where
+        Self: Sized,
+    {
+        combinators::Join(self, other, PhantomData)
+    }
+
+    /// Runs the fetcher returned by `fallback` if this one fails.
+    fn or_else<F>(self, fallback: F) -> combinators::OrElse<Self, F, Doc>
+    where
+        Self: Sized,
+    {
+        combinators::OrElse(self, fallback, PhantomData)
+    }
+
+    /// Turns the failures `missing` accepts into `None`, such as a lookup finding nothing. Other
+    /// failures are kept.
+    fn optional<P>(self, missing: P) -> combinators::Optional<Self, P, Doc>
+    where
+        Self: Sized,
+        P: FnOnce(&Self::Error) -> bool,
+    {
+        combinators::Optional(self, missing, PhantomData)
+    }
+
+    /// Wraps the fetched documents in [`tracking::Tracked`], to be saved with minimal updates.
+    fn tracked(self) -> tracking::Track<Self, Doc>
+    where
+        Self: Sized,
+    {
+        tracking::Track(self, PhantomData)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::future::Future;
+
     use mongodb::bson::{doc, oid::ObjectId};
 
     use crate::{Collection, Document, Fetcher};
@@ -39,17 +175,20 @@ mod tests {
         type Internal = mongodb::Collection<Self::Document>;
         type Document = Doc;
 
-        async fn fetch<F: Fetcher<Self::Document, Self::Internal>>(
+        fn fetch<F: Fetcher<Self::Document, Self::Internal>>(
             &self,
             f: F,
-        ) -> Result<F::Output, F::Error> {
-            f.fetch(&self.0).await
+        ) -> impl Future<Output = Result<F::Output, F::Error>> + Send {
+            f.fetch(&self.0)
         }
     }
 
     pub struct ObjectIdFetcher(ObjectId);
 
-    impl<Doc: serde::de::DeserializeOwned> Fetcher<Doc, mongodb::Collection<Doc>> for ObjectIdFetcher {
+    impl<Doc> Fetcher<Doc, mongodb::Collection<Doc>> for ObjectIdFetcher
+    where
+        Doc: serde::de::DeserializeOwned + Send + Sync,
+    {
         type Output = Option<Doc>;
         type Error = mongodb::error::Error;
 
@@ -71,6 +210,8 @@ mod tests {
 
     impl Document for Doc {
         type Collection = Coll;
+        type Id = crate::id::ObjectIdStrategy;
+        type Index = std::convert::Infallible;
     }
 
     #[test]
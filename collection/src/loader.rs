@@ -0,0 +1,260 @@
+//! Batching of `ById` lookups, in the style of DataLoader.
+//!
+//! A [`Loader`] is meant to live for a single request. Every [`Loader::load`] issued before the
+//! executor next gets around to them is collected into one [`ByIds`] fetch, and the results,
+//! including misses, are cached for the rest of the loader's life.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_util::lock::Mutex as AsyncMutex;
+use mongodb::bson::Bson;
+
+use crate::fetchers::{ById, ByIds};
+use crate::id::{self, IntoId};
+use crate::mongo::ReadableCollection;
+use crate::Error;
+
+pub struct Loader<'a, C: ReadableCollection> {
+    coll: &'a C,
+    batches: Batches<C::Document>,
+}
+
+impl<'a, C: ReadableCollection> Loader<'a, C> {
+    pub fn new(coll: &'a C) -> Self {
+        Self {
+            coll,
+            batches: Batches::new(),
+        }
+    }
+
+    /// Loads a document through the current batch, or the cache if it was loaded before.
+    pub async fn load<K: IntoId<C::Document>>(
+        &self,
+        f: ById<K>,
+    ) -> Result<Option<C::Document>, Error>
+    where
+        C::Document: Clone,
+    {
+        self.batches
+            .load(f.0.into(), |ids| self.coll.fetch(ByIds(ids)))
+            .await
+    }
+
+    /// Forgets everything loaded so far.
+    pub fn clear(&self) {
+        self.batches.clear();
+    }
+}
+
+/// The batches and cache of a loader, whichever way a batch is fetched.
+struct Batches<D> {
+    state: Mutex<State<D>>,
+}
+
+struct State<D> {
+    cache: HashMap<String, Option<D>>,
+    pending: Option<Arc<Batch<D>>>,
+}
+
+/// The ids of a batch and, once fetched, what was found for each. The ids stay until the batch
+/// is done, so another caller can fetch them if the first one is dropped mid-fetch.
+struct Batch<D> {
+    ids: Mutex<HashMap<String, Bson>>,
+    done: AsyncMutex<Option<Result<Found<D>, Arc<Error>>>>,
+}
+
+/// What a fetched batch found, by the key of each id.
+type Found<D> = HashMap<String, Option<D>>;
+
+impl<D> Batch<D> {
+    fn new() -> Self {
+        Self {
+            ids: Mutex::new(HashMap::new()),
+            done: AsyncMutex::new(None),
+        }
+    }
+}
+
+impl<D> Batches<D> {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                cache: HashMap::new(),
+                pending: None,
+            }),
+        }
+    }
+
+    fn clear(&self) {
+        self.state.lock().unwrap().cache.clear();
+    }
+}
+
+impl<D: Clone> Batches<D> {
+    /// Loads `id` through the current batch, which the first caller through fetches with `fetch`
+    /// for everyone in it.
+    async fn load<F, Fut>(&self, id: Bson, fetch: F) -> Result<Option<D>, Error>
+    where
+        F: Fn(Vec<Bson>) -> Fut,
+        Fut: Future<Output = Result<Vec<Option<D>>, Error>>,
+    {
+        let key = id::key(&id);
+
+        // A batch only lacks the id if it was fetched before the id joined it; go again then.
+        loop {
+            let batch = {
+                let mut state = self.state.lock().unwrap();
+                if let Some(hit) = state.cache.get(&key) {
+                    return Ok(hit.clone());
+                }
+
+                let batch = state
+                    .pending
+                    .get_or_insert_with(|| Arc::new(Batch::new()))
+                    .clone();
+                batch.ids.lock().unwrap().insert(key.clone(), id.clone());
+                batch
+            };
+
+            YieldNow(false).await;
+
+            let mut done = batch.done.lock().await;
+            if done.is_none() {
+                {
+                    let mut state = self.state.lock().unwrap();
+                    if state
+                        .pending
+                        .as_ref()
+                        .is_some_and(|p| Arc::ptr_eq(p, &batch))
+                    {
+                        state.pending = None;
+                    }
+                }
+
+                let (keys, ids): (Vec<_>, Vec<_>) = batch
+                    .ids
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(key, id)| (key.clone(), id.clone()))
+                    .unzip();
+
+                *done = Some(match fetch(ids).await {
+                    Ok(docs) => {
+                        let found = keys.into_iter().zip(docs).collect::<Found<D>>();
+                        self.state.lock().unwrap().cache.extend(found.clone());
+                        Ok(found)
+                    }
+                    Err(e) => Err(Arc::new(e)),
+                });
+            }
+
+            match done.as_ref().unwrap() {
+                Ok(found) => {
+                    if let Some(doc) = found.get(&key) {
+                        return Ok(doc.clone());
+                    }
+                }
+                Err(e) => return Err(Error::Batch(e.clone())),
+            }
+        }
+    }
+}
+
+/// Gives every other task polled alongside this one a chance to queue its loads.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::pin;
+    use std::sync::Mutex;
+    use std::task::{Context, Poll, Waker};
+
+    use futures_util::future;
+    use mongodb::bson::Bson;
+
+    use super::Batches;
+    use crate::{id, Error};
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn it_batches_loads() {
+        let batches = Batches::<String>::new();
+        let calls = Mutex::new(Vec::new());
+        let fetch = |ids: Vec<Bson>| {
+            calls.lock().unwrap().push(ids.len());
+            let docs = ids
+                .iter()
+                .map(|id| Some(id::key(id)).filter(|key| key != "3"))
+                .collect();
+            async move { Ok(docs) }
+        };
+
+        let loads = block_on(future::join4(
+            batches.load(Bson::Int32(1), fetch),
+            batches.load(Bson::Int64(1), fetch),
+            batches.load(Bson::Int32(2), fetch),
+            batches.load(Bson::Int32(3), fetch),
+        ));
+        assert_eq!(*calls.lock().unwrap(), [3]);
+        assert_eq!(loads.0.unwrap().as_deref(), Some("1"));
+        assert_eq!(loads.1.unwrap().as_deref(), Some("1"));
+        assert_eq!(loads.2.unwrap().as_deref(), Some("2"));
+        assert_eq!(loads.3.unwrap(), None);
+
+        assert_eq!(block_on(batches.load(Bson::Int32(3), fetch)).unwrap(), None);
+        assert_eq!(*calls.lock().unwrap(), [3]);
+
+        batches.clear();
+        block_on(batches.load(Bson::Int32(3), fetch)).unwrap();
+        assert_eq!(*calls.lock().unwrap(), [3, 1]);
+
+        // The batch of a caller dropped mid-fetch is fetched by the next one in it.
+        let stalled = |_| future::pending::<Result<Vec<Option<String>>, Error>>();
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut first = Box::pin(batches.load(Bson::Int32(4), stalled));
+        let mut second = Box::pin(batches.load(Bson::Int32(5), fetch));
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        assert!(second.as_mut().poll(&mut cx).is_pending());
+        assert!(first.as_mut().poll(&mut cx).is_pending());
+        drop(first);
+
+        assert_eq!(block_on(second).unwrap().as_deref(), Some("5"));
+        assert_eq!(*calls.lock().unwrap(), [3, 1, 2]);
+        assert_eq!(
+            block_on(batches.load(Bson::Int32(4), fetch))
+                .unwrap()
+                .as_deref(),
+            Some("4")
+        );
+        assert_eq!(*calls.lock().unwrap(), [3, 1, 2]);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+
+use mongodb::bson::{self, doc, Bson};
+use mongodb::options::{FindOptions, UpdateOptions};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{Document, Error};
+
+/// Field holding the schema version of a stored document. Documents without it are version 0.
+pub const VERSION_FIELD: &str = "_v";
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+type Step = Box<dyn Fn(bson::Document) -> Result<bson::Document, BoxError> + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("No migration registered from version {0}")]
+    Missing(u32),
+    #[error("Document version {found} is newer than the current version {current}")]
+    Newer { found: u32, current: u32 },
+    #[error("Migration from version {from} failed: {source}")]
+    Step { from: u32, source: BoxError },
+    #[error("Stored document version {0} is not a valid version")]
+    Invalid(Bson),
+}
+
+/// Implemented by documents declaring `#[coll(version = N)]` to provide their migration steps.
+pub trait Migrate {
+    fn migrations() -> Migrations;
+}
+
+/// Registry of steps, each taking a document from version `n` to `n + 1`.
+#[derive(Default)]
+pub struct Migrations {
+    steps: BTreeMap<u32, Step>,
+}
+
+impl Migrations {
+    pub const fn new() -> Self {
+        Self {
+            steps: BTreeMap::new(),
+        }
+    }
+
+    pub fn step<E: Into<BoxError>>(
+        mut self,
+        from: u32,
+        f: impl Fn(bson::Document) -> Result<bson::Document, E> + Send + Sync + 'static,
+    ) -> Self {
+        self.steps
+            .insert(from, Box::new(move |doc| f(doc).map_err(Into::into)));
+        self
+    }
+
+    /// Registers a step going through the `From` impl between two versions of the struct, such as
+    /// the ones generated by `profile`.
+    pub fn typed<Old, New>(self, from: u32) -> Self
+    where
+        Old: DeserializeOwned,
+        New: Serialize + From<Old>,
+    {
+        self.step(from, |doc| -> Result<_, BoxError> {
+            let old: Old = bson::from_document(doc)?;
+            Ok(bson::to_document(&New::from(old))?)
+        })
+    }
+
+    pub fn apply(
+        &self,
+        mut doc: bson::Document,
+        to: u32,
+    ) -> Result<bson::Document, MigrationError> {
+        let mut at = version_of(&doc)?;
+
+        if at > to {
+            return Err(MigrationError::Newer {
+                found: at,
+                current: to,
+            });
+        }
+
+        while at < to {
+            let step = self.steps.get(&at).ok_or(MigrationError::Missing(at))?;
+            doc = step(doc).map_err(|source| MigrationError::Step { from: at, source })?;
+            at += 1;
+        }
+
+        doc.insert(VERSION_FIELD, i64::from(to));
+        Ok(doc)
+    }
+}
+
+/// The version a document was stored with, failing on versions no document can have.
+pub fn version_of(doc: &bson::Document) -> Result<u32, MigrationError> {
+    let stored = match doc.get(VERSION_FIELD) {
+        None | Some(Bson::Null) => return Ok(0),
+        Some(stored) => stored,
+    };
+    let version = match stored {
+        Bson::Int32(v) => u32::try_from(*v).ok(),
+        Bson::Int64(v) => u32::try_from(*v).ok(),
+        _ => None,
+    };
+    version.ok_or_else(|| MigrationError::Invalid(stored.clone()))
+}
+
+/// Deserializes a stored document, migrating it to the current version first.
+pub fn decode<D: Document + DeserializeOwned>(raw: bson::Document) -> Result<D, Error> {
+    let raw = if D::VERSION > 0 {
+        D::migrations().apply(raw, D::VERSION)?
+    } else {
+        raw
+    };
+
+    Ok(bson::from_document(raw)?)
+}
+
+/// Serializes a document, stamping it with the current version.
+pub fn encode<D: Document + Serialize>(doc: &D) -> Result<bson::Document, Error> {
+    let mut raw = bson::to_document(doc)?;
+
+    if D::VERSION > 0 {
+        raw.insert(VERSION_FIELD, i64::from(D::VERSION));
+    }
+    Ok(raw)
+}
+
+/// Migrates every outdated document in `coll` to the current version.
+///
+/// Progress is recorded in `progress` under the collection name, so an interrupted run continues
+/// after the last migrated `_id`. Returns the number of documents migrated by this run.
+pub async fn run<D: Document>(
+    coll: &mongodb::Collection<D>,
+    progress: &mongodb::Collection<bson::Document>,
+) -> Result<u64, Error> {
+    let raw = coll.clone_with_type::<bson::Document>();
+    let key = coll.name();
+    let target = i64::from(D::VERSION);
+
+    let mut filter = doc! { VERSION_FIELD: { "$not": { "$gte": target } } };
+    if let Some(state) = progress.find_one(doc! { "_id": key }, None).await? {
+        if state.get_i64("target") == Ok(target) {
+            if let Some(last) = state.get("last_id") {
+                filter.insert("_id", doc! { "$gt": last });
+            }
+        }
+    }
+
+    let mut opts = FindOptions::default();
+    opts.sort = Some(doc! { "_id": 1 });
+    let mut cursor = raw.find(filter, opts).await?;
+
+    let mut upsert = UpdateOptions::default();
+    upsert.upsert = Some(true);
+
+    let mut migrated = 0;
+    while cursor.advance().await? {
+        let doc = cursor.deserialize_current()?;
+        let id = doc.get("_id").cloned().unwrap_or(Bson::Null);
+
+        let from = version_of(&doc)?;
+        let doc = D::migrations().apply(doc, D::VERSION)?;
+
+        let guard = match from {
+            0 => doc! { "$in": [Bson::Null, 0] },
+            v => doc! { "$eq": i64::from(v) },
+        };
+        raw.replace_one(doc! { "_id": &id, VERSION_FIELD: guard }, doc, None)
+            .await?;
+        migrated += 1;
+
+        progress
+            .update_one(
+                doc! { "_id": key },
+                doc! {
+                    "$set": { "target": target, "last_id": id },
+                    "$inc": { "migrated": 1_i64 },
+                },
+                upsert.clone(),
+            )
+            .await?;
+    }
+
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::{version_of, MigrationError, Migrations};
+
+    #[test]
+    fn it_applies_steps_in_order() {
+        let migrations = Migrations::new()
+            .step(0, |mut doc| {
+                doc.insert("name", "unknown");
+                Ok::<_, MigrationError>(doc)
+            })
+            .step(1, |mut doc| {
+                let name = doc.remove("name");
+                doc.insert("display_name", name);
+                Ok::<_, MigrationError>(doc)
+            });
+
+        let doc = migrations.apply(doc! { "a": 1 }, 2).unwrap();
+
+        assert_eq!(version_of(&doc).unwrap(), 2);
+        assert_eq!(doc.get_str("display_name"), Ok("unknown"));
+        assert!(matches!(
+            migrations.apply(doc, 1),
+            Err(MigrationError::Newer { found: 2, .. })
+        ));
+        assert!(matches!(
+            migrations.apply(doc! {}, 3),
+            Err(MigrationError::Missing(2))
+        ));
+        assert!(matches!(
+            migrations.apply(doc! { "_v": -1 }, 2),
+            Err(MigrationError::Invalid(_))
+        ));
+        assert!(matches!(
+            version_of(&doc! { "_v": i64::MAX }),
+            Err(MigrationError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,687 @@
+use std::future::Future;
+use std::time::Duration;
+
+use mongodb::bson::{self, doc, Bson, DateTime};
+use mongodb::options::{
+    AggregateOptions, CollectionOptions, CountOptions, DistinctOptions, FindOneAndReplaceOptions,
+    FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReadPreference, ReplaceOptions,
+    ReturnDocument, SelectionCriteria, UpdateOptions,
+};
+use mongodb::Cursor;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::cache::Cached;
+use crate::error::{IndexCreationError, WriteError};
+use crate::explain::{self, Plan, Verbosity};
+use crate::fetchers::ById;
+use crate::history::{History, Op};
+use crate::hooks::{self, Hook};
+use crate::id::{self, Id, IntoId};
+use crate::index::Index;
+use crate::loader::Loader;
+use crate::sequence::{self, Counters, Sequenced, Sequences};
+use crate::soft_delete::Scope;
+use crate::timestamps::Timestamps;
+use crate::tracking::Tracked;
+use crate::{concurrency, context, migrate, Collection, Document, Error, Fetcher};
+
+/// Passed to the driver handles of a [`ReadableCollection`], which can only be reached from within
+/// the ORM since nothing else can build one. Otherwise a read-only handle could be written through:
+///
+/// ```compile_fail
+/// use collection::mongo::{ReadOnly, ReadableCollection, Token};
+///
+/// fn write<D>(ro: &ReadOnly<D>) -> &mongodb::Collection<D>
+/// where
+///     ReadOnly<D>: ReadableCollection<Document = D>,
+/// {
+///     ro.inner(Token(()))
+/// }
+/// ```
+///
+/// Implementations only pass it on.
+#[derive(Debug, Clone, Copy)]
+pub struct Token(());
+
+pub(crate) const TOKEN: Token = Token(());
+
+/// A collection backed by MongoDB which can be read from.
+pub trait ReadableCollection:
+    Collection<
+        Internal: Source<<Self as Collection>::Document>,
+        Document: Serialize + DeserializeOwned + Unpin + Send + Sync,
+    > + Send
+    + Sync
+{
+    #[doc(hidden)]
+    fn inner(&self, token: Token) -> &mongodb::Collection<Self::Document>;
+    #[doc(hidden)]
+    fn database(&self, token: Token) -> &mongodb::Database;
+
+    fn ensure_indicies(&self) -> impl Future<Output = Result<(), IndexCreationError>> + Send {
+        async move {
+            let models = <<Self::Document as Document>::Index as Index>::ALL
+                .iter()
+                .map(|index| index.model())
+                .collect::<Vec<_>>();
+
+            if !models.is_empty() {
+                self.inner(TOKEN).create_indexes(models, None).await?;
+            }
+            if Self::Document::HISTORY {
+                self.history()
+                    .0
+                    .create_index(History::<Self::Document>::index(), None)
+                    .await?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Explains the query of `f` at `verbosity`, `None` if `f` doesn't run a query by filter.
+    fn explain<F>(
+        &self,
+        f: F,
+        verbosity: Verbosity,
+    ) -> impl Future<Output = Result<Option<Plan>, Error>> + Send
+    where
+        F: Fetcher<Self::Document, Self::Internal>,
+    {
+        let query = f.query();
+        async move {
+            let Some(query) = query else {
+                return Ok(None);
+            };
+            let plan = explain::run::<Self::Document>(
+                self.database(TOKEN),
+                self.inner(TOKEN).name(),
+                &query,
+                verbosity,
+            );
+            Ok(Some(plan.await?))
+        }
+    }
+
+    /// The history of the documents, see [`history`](crate::history).
+    fn history(&self) -> History<Self::Document> {
+        History::new(self.database(TOKEN), self.inner(TOKEN).name())
+    }
+
+    /// A loader batching `ById` lookups, meant to live for one request.
+    fn loader(&self) -> Loader<'_, Self>
+    where
+        Self: Sized,
+    {
+        Loader::new(self)
+    }
+
+    /// Wraps the collection so `ById` and `FindOne` lookups are cached, see [`Cached`].
+    fn cached(self, capacity: usize, ttl: Duration) -> Cached<Self>
+    where
+        Self: Sized,
+    {
+        Cached::new(self, capacity, ttl)
+    }
+}
+
+/// The result of writes to `C`, see [`WriteError`].
+pub type WriteResult<C, T> =
+    Result<T, WriteError<<<C as Collection>::Document as Document>::Index>>;
+
+/// A collection which can also be written to. All ORM writes go through here.
+pub trait WritableCollection: ReadableCollection {
+    fn raw(&self) -> mongodb::Collection<bson::Document> {
+        self.inner(TOKEN).clone_with_type()
+    }
+
+    /// Where `#[coll(sequence = ...)]` fields are numbered from, the [`Counters`] of the
+    /// database by default.
+    fn sequences(&self) -> impl Sequences + '_ {
+        Counters::new(self.database(TOKEN))
+    }
+
+    /// Called after writes which go around the methods of the collection, such as a
+    /// [`Bulk`](crate::bulk::Bulk) run, without telling which documents they changed.
+    fn written(&self) {}
+
+    /// Wraps the collection so sequence fields are numbered from `seq`, see [`Sequenced`].
+    fn with_sequences<S: Sequences>(self, seq: S) -> Sequenced<Self, S>
+    where
+        Self: Sized,
+    {
+        Sequenced::new(self, seq)
+    }
+
+    /// Inserts the document, returning its `_id`, which is generated if the document has none.
+    /// Missing sequence fields are numbered and missing timestamps filled in.
+    fn insert(
+        &self,
+        doc: &Self::Document,
+    ) -> impl Future<Output = WriteResult<Self, Id<Self::Document>>> + Send {
+        async move {
+            let mut raw = migrate::encode(doc)?;
+            id::ensure_id::<Self::Document>(&mut raw);
+            Self::Document::TIMESTAMPS.on_insert(&mut raw, DateTime::now());
+            hooks::call_raw::<Self::Document>(&mut raw, Hook::BeforeInsert).await?;
+            hooks::validate(doc, &raw)?;
+            sequence::assign::<Self::Document, _>(&self.sequences(), vec![&mut raw]).await?;
+            let id = bson::from_bson(raw.get("_id").cloned().unwrap_or(Bson::Null))?;
+
+            context::insert_one(&self.raw(), &raw).await?;
+            record(self, Op::Insert, None, Some(raw.clone())).await?;
+            hooks::call_raw::<Self::Document>(&mut raw, Hook::AfterInsert).await?;
+            Ok(id)
+        }
+    }
+
+    fn insert_many(
+        &self,
+        docs: &[Self::Document],
+    ) -> impl Future<Output = WriteResult<Self, Vec<Id<Self::Document>>>> + Send {
+        async move {
+            let now = DateTime::now();
+            let mut raw = Vec::with_capacity(docs.len());
+            for doc in docs {
+                let mut raw_doc = migrate::encode(doc)?;
+                id::ensure_id::<Self::Document>(&mut raw_doc);
+                Self::Document::TIMESTAMPS.on_insert(&mut raw_doc, now);
+                hooks::call_raw::<Self::Document>(&mut raw_doc, Hook::BeforeInsert).await?;
+                hooks::validate(doc, &raw_doc)?;
+                raw.push(raw_doc);
+            }
+            sequence::assign::<Self::Document, _>(&self.sequences(), raw.iter_mut().collect())
+                .await?;
+            let ids = raw
+                .iter()
+                .map(|doc| bson::from_bson(doc.get("_id").cloned().unwrap_or(Bson::Null)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            context::insert_many(&self.raw(), &raw).await?;
+            for doc in &mut raw {
+                record(self, Op::Insert, None, Some(doc.clone())).await?;
+                hooks::call_raw::<Self::Document>(doc, Hook::AfterInsert).await?;
+            }
+            Ok(ids)
+        }
+    }
+
+    /// Replaces the stored document with the same `_id`, returning whether one was found.
+    /// Versioned documents are only replaced at the version they were read at.
+    fn replace(
+        &self,
+        doc: &Self::Document,
+    ) -> impl Future<Output = WriteResult<Self, bool>> + Send {
+        async move {
+            let mut raw = migrate::encode(doc)?;
+            let filter = concurrency::filter::<Self::Document>(&raw);
+            concurrency::bump::<Self::Document>(&mut raw);
+            Self::Document::TIMESTAMPS.on_replace(&mut raw, DateTime::now());
+            hooks::call_raw::<Self::Document>(&mut raw, Hook::BeforeUpdate).await?;
+            hooks::validate(doc, &raw)?;
+
+            replace_raw(self, filter, raw).await
+        }
+    }
+
+    /// Replaces the stored document with the same `_id`, inserting it if there is none. Unlike
+    /// [`replace`](Self::replace) this doesn't check the version, but still bumps it. Documents
+    /// with timestamps are upserted with `$set` instead, keeping the stored `created_at`.
+    fn upsert(&self, doc: &Self::Document) -> impl Future<Output = WriteResult<Self, ()>> + Send {
+        async move {
+            let mut raw = migrate::encode(doc)?;
+            let mut id = id::ensure_id::<Self::Document>(&mut raw);
+            concurrency::bump::<Self::Document>(&mut raw);
+            hooks::call_raw::<Self::Document>(&mut raw, Hook::BeforeUpdate).await?;
+            hooks::validate(doc, &raw)?;
+            if id == Bson::Null {
+                // Only new documents lack an id numbered from a sequence.
+                sequence::assign::<Self::Document, _>(&self.sequences(), vec![&mut raw]).await?;
+                id = raw.get("_id").cloned().unwrap_or(Bson::Null);
+            }
+
+            let filter = doc! { "_id": id };
+            let stamps = Self::Document::TIMESTAMPS;
+            if !Self::Document::HISTORY {
+                if stamps == Timestamps::NONE {
+                    let mut opts = ReplaceOptions::default();
+                    opts.upsert = Some(true);
+
+                    context::replace_one(&self.raw(), filter, &raw, opts).await?;
+                } else {
+                    let mut opts = UpdateOptions::default();
+                    opts.upsert = Some(true);
+
+                    let update = stamps.upsert(raw, DateTime::now());
+                    context::update_one(&self.raw(), filter, update, opts).await?;
+                }
+                return Ok(());
+            }
+
+            // The recorded before is read by the write itself, so it is the document it replaced.
+            let before = if stamps == Timestamps::NONE {
+                let mut opts = FindOneAndReplaceOptions::default();
+                opts.upsert = Some(true);
+                opts.return_document = Some(ReturnDocument::Before);
+
+                context::find_one_and_replace(&self.raw(), filter.clone(), &raw, opts).await?
+            } else {
+                let mut opts = FindOneAndUpdateOptions::default();
+                opts.upsert = Some(true);
+                opts.return_document = Some(ReturnDocument::Before);
+
+                let update = stamps.upsert(raw, DateTime::now());
+                context::find_one_and_update(&self.raw(), filter.clone(), update, opts).await?
+            };
+
+            let after = snapshot(self, filter).await?;
+            let op = if before.is_some() {
+                Op::Replace
+            } else {
+                Op::Insert
+            };
+            record(self, op, before, after).await
+        }
+    }
+
+    /// Applies `update` to the first document matching `filter`, bumping its version and
+    /// `updated_at`. Returns whether one was found. Hooks are not run, see [`hooks`].
+    fn update(
+        &self,
+        filter: bson::Document,
+        update: bson::Document,
+    ) -> impl Future<Output = WriteResult<Self, bool>> + Send {
+        async move { Ok(apply(self, filter, update, Op::Update).await? > 0) }
+    }
+
+    /// Applies `update` to the stored `doc`, at the version it was read at if it is versioned.
+    /// Returns whether the document was found. Hooks are not run, see [`hooks`].
+    fn update_doc(
+        &self,
+        doc: &Self::Document,
+        update: bson::Document,
+    ) -> impl Future<Output = WriteResult<Self, bool>> + Send {
+        async move {
+            let filter = concurrency::filter::<Self::Document>(&migrate::encode(doc)?);
+            let matched = apply(self, filter.clone(), update, Op::Update).await?;
+            concurrency::check::<Self::Document>(&self.raw(), filter, matched).await
+        }
+    }
+
+    /// Writes the fields of the document which changed since it was fetched or last saved, at
+    /// the version it was read at if it is versioned. Returns whether the document was found;
+    /// unchanged documents aren't written. Changes `before_update` makes are kept in `doc`.
+    /// Documents stored before the last migration are replaced whole, at the current version.
+    fn save(
+        &self,
+        doc: &mut Tracked<Self::Document>,
+    ) -> impl Future<Output = WriteResult<Self, bool>> + Send {
+        async move {
+            if !doc.is_dirty()? {
+                return Ok(true);
+            }
+            hooks::call(&mut **doc, Hook::BeforeUpdate).await?;
+            let update = doc.changes()?;
+            Self::Document::validate_fields(doc).map_err(Error::from)?;
+
+            let filter = concurrency::filter::<Self::Document>(doc.original());
+            let found = if Self::Document::VERSION > 0 {
+                // The original is the migrated document, so the changes miss whatever migrating
+                // changed. Documents still stored at an older version are written whole instead.
+                let mut current = filter.clone();
+                current.insert(migrate::VERSION_FIELD, i64::from(Self::Document::VERSION));
+                match apply(self, current, update, Op::Update).await? {
+                    0 => {
+                        let mut raw = migrate::encode(&**doc)?;
+                        concurrency::bump::<Self::Document>(&mut raw);
+                        Self::Document::TIMESTAMPS.on_replace(&mut raw, DateTime::now());
+                        replace_raw(self, filter, raw).await?
+                    }
+                    _ => true,
+                }
+            } else {
+                let matched = apply(self, filter.clone(), update, Op::Update).await?;
+                concurrency::check::<Self::Document>(&self.raw(), filter, matched).await?
+            };
+            if found {
+                doc.saved()?;
+            }
+            Ok(found)
+        }
+    }
+
+    /// Fetches the document, applies `f` to it and replaces it, starting over from a fresh read
+    /// up to `attempts` times while it is modified concurrently. Returns the result of the last
+    /// `f`, or `None` if there is no such document.
+    fn modify<K, F, T>(
+        &self,
+        id: K,
+        attempts: usize,
+        mut f: F,
+    ) -> impl Future<Output = WriteResult<Self, Option<T>>> + Send
+    where
+        K: IntoId<Self::Document> + Clone + Send + Sync,
+        F: FnMut(&mut Self::Document) -> T + Send,
+        T: Send,
+    {
+        async move {
+            let mut attempt = 1;
+            loop {
+                let Some(mut doc) = self.fetch(ById(id.clone())).await? else {
+                    return Ok(None);
+                };
+                let out = f(&mut doc);
+
+                match self.replace(&doc).await {
+                    Ok(true) => return Ok(Some(out)),
+                    Ok(false) => return Ok(None),
+                    Err(WriteError::ConcurrentModification { .. }) if attempt < attempts => {
+                        attempt += 1
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+
+    /// Deletes the first document matching `filter`, returning whether one was found. Documents
+    /// with soft delete are stamped as deleted instead.
+    fn delete(
+        &self,
+        mut filter: bson::Document,
+    ) -> impl Future<Output = WriteResult<Self, bool>> + Send {
+        async move {
+            // The after_delete hook and the history need the document, so it is looked up first
+            // and deleted by its `_id`.
+            let mut target = None;
+            if Self::Document::HOOKS || Self::Document::HISTORY {
+                Scope::Active.apply::<Self::Document>(&mut filter);
+                let Some(raw) = context::find_one(&self.raw(), filter).await? else {
+                    return Ok(false);
+                };
+                filter = doc! { "_id": raw.get("_id").cloned().unwrap_or(Bson::Null) };
+                target = Some(raw);
+            }
+
+            let deleted = match Self::Document::SOFT_DELETE {
+                None => {
+                    let deleted = context::delete_one(&self.raw(), filter)
+                        .await?
+                        .deleted_count
+                        > 0;
+                    if deleted {
+                        record(self, Op::Delete, target.clone(), None).await?;
+                    }
+                    deleted
+                }
+                Some(field) => {
+                    Scope::Active.apply::<Self::Document>(&mut filter);
+                    let update = doc! { "$currentDate": { field: true } };
+                    apply(self, filter, update, Op::Delete).await? > 0
+                }
+            };
+
+            if let Some(mut raw) = target.filter(|_| deleted) {
+                hooks::call_raw::<Self::Document>(&mut raw, Hook::AfterDelete).await?;
+            }
+            Ok(deleted)
+        }
+    }
+
+    /// Brings back the first soft deleted document matching `filter`, returning whether one was
+    /// found.
+    fn restore(
+        &self,
+        mut filter: bson::Document,
+    ) -> impl Future<Output = WriteResult<Self, bool>> + Send {
+        async move {
+            let Some(field) = Self::Document::SOFT_DELETE else {
+                return Ok(false);
+            };
+
+            Scope::OnlyDeleted.apply::<Self::Document>(&mut filter);
+            let update = doc! { "$unset": { field: "" } };
+            Ok(apply(self, filter, update, Op::Restore).await? > 0)
+        }
+    }
+}
+
+/// Applies `update` to the first document matching `filter`, bumping its version and
+/// `updated_at`, and records the change as `op`. Returns the number of documents matched.
+async fn apply<C: WritableCollection + ?Sized>(
+    coll: &C,
+    filter: bson::Document,
+    mut update: bson::Document,
+    op: Op,
+) -> WriteResult<C, u64> {
+    concurrency::inc::<C::Document>(&mut update);
+    C::Document::TIMESTAMPS.on_update(&mut update, DateTime::now());
+
+    if !C::Document::HISTORY {
+        let res = context::update_one(&coll.raw(), filter, update, Default::default()).await?;
+        return Ok(res.matched_count);
+    }
+
+    // The recorded before is read by the update itself, so it is the document it changed.
+    let mut opts = FindOneAndUpdateOptions::default();
+    opts.return_document = Some(ReturnDocument::Before);
+    let Some(before) = context::find_one_and_update(&coll.raw(), filter, update, opts).await?
+    else {
+        return Ok(0);
+    };
+
+    let after = snapshot(coll, doc! { "_id": before.get("_id").cloned() }).await?;
+    record(coll, op, Some(before), after).await?;
+    Ok(1)
+}
+
+/// Replaces the document matching `filter` with `raw`, recording the change. Returns whether one
+/// was found, see [`concurrency::check`].
+async fn replace_raw<C: WritableCollection + ?Sized>(
+    coll: &C,
+    filter: bson::Document,
+    raw: bson::Document,
+) -> WriteResult<C, bool> {
+    if !C::Document::HISTORY {
+        let res =
+            context::replace_one(&coll.raw(), filter.clone(), &raw, Default::default()).await?;
+        return concurrency::check::<C::Document>(&coll.raw(), filter, res.matched_count).await;
+    }
+
+    let mut opts = FindOneAndReplaceOptions::default();
+    opts.return_document = Some(ReturnDocument::Before);
+    let before = context::find_one_and_replace(&coll.raw(), filter.clone(), &raw, opts).await?;
+    let matched = u64::from(before.is_some());
+    if before.is_some() {
+        record(coll, Op::Replace, before, Some(raw)).await?;
+    }
+    concurrency::check::<C::Document>(&coll.raw(), filter, matched).await
+}
+
+/// The stored document matching `filter`, if its changes are recorded in the history.
+async fn snapshot<C: WritableCollection + ?Sized>(
+    coll: &C,
+    filter: bson::Document,
+) -> WriteResult<C, Option<bson::Document>> {
+    if !C::Document::HISTORY {
+        return Ok(None);
+    }
+    Ok(context::find_one(&coll.raw(), filter).await?)
+}
+
+async fn record<C: WritableCollection + ?Sized>(
+    coll: &C,
+    op: Op,
+    before: Option<bson::Document>,
+    after: Option<bson::Document>,
+) -> WriteResult<C, ()> {
+    if C::Document::HISTORY {
+        coll.history().record(op, before, after).await?;
+    }
+    Ok(())
+}
+
+/// A handle which can only be used to read, see the generated `read_only` method. Reads prefer
+/// secondaries unless other selection criteria are given.
+pub struct ReadOnly<D> {
+    reader: Reader<D>,
+    db: mongodb::Database,
+}
+
+impl<D> Clone for ReadOnly<D> {
+    fn clone(&self) -> Self {
+        Self {
+            reader: self.reader.clone(),
+            db: self.db.clone(),
+        }
+    }
+}
+
+impl<D> ReadOnly<D> {
+    /// A read-only handle to `coll`, keeping its read and write concerns.
+    pub fn new(
+        db: &mongodb::Database,
+        coll: &mongodb::Collection<D>,
+        criteria: Option<SelectionCriteria>,
+    ) -> Self {
+        let criteria = criteria.unwrap_or(SelectionCriteria::ReadPreference(
+            ReadPreference::SecondaryPreferred {
+                options: Default::default(),
+            },
+        ));
+
+        let mut opts = CollectionOptions::default();
+        opts.selection_criteria = Some(criteria);
+        opts.read_concern = coll.read_concern().cloned();
+        opts.write_concern = coll.write_concern().cloned();
+
+        Self {
+            reader: Reader(db.collection_with_options(coll.name(), opts)),
+            db: db.clone(),
+        }
+    }
+
+    /// Which servers reads are sent to.
+    pub fn selection_criteria(&self) -> Option<&SelectionCriteria> {
+        self.reader.0.selection_criteria()
+    }
+}
+
+impl<D: Document> Collection for ReadOnly<D> {
+    type Internal = Reader<D>;
+    type Document = D;
+
+    fn fetch<F: Fetcher<Self::Document, Self::Internal>>(
+        &self,
+        f: F,
+    ) -> impl Future<Output = Result<F::Output, F::Error>> + Send {
+        let check = explain::check::<D, _, _>(&self.db, self.reader.name(), &f);
+        let fetch = f.fetch(&self.reader);
+        async move {
+            check.await;
+            fetch.await
+        }
+    }
+}
+
+impl<D> ReadableCollection for ReadOnly<D>
+where
+    D: Document + Serialize + DeserializeOwned + Unpin + Send + Sync,
+{
+    fn inner(&self, _: Token) -> &mongodb::Collection<D> {
+        &self.reader.0
+    }
+    fn database(&self, _: Token) -> &mongodb::Database {
+        &self.db
+    }
+}
+
+/// The reads of a collection, which the fetchers of a [`ReadOnly`] collection run against. There
+/// is nothing to write with:
+///
+/// ```compile_fail
+/// use collection::mongo::Reader;
+///
+/// async fn write(reader: &Reader<mongodb::bson::Document>) {
+///     reader.insert_one(mongodb::bson::doc! {}, None).await;
+/// }
+/// ```
+pub struct Reader<D>(mongodb::Collection<D>);
+
+impl<D> Clone for Reader<D> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<D> Reader<D> {
+    pub fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    pub fn clone_with_type<T>(&self) -> Reader<T> {
+        Reader(self.0.clone_with_type())
+    }
+
+    pub async fn aggregate(
+        &self,
+        pipeline: impl IntoIterator<Item = bson::Document>,
+        options: impl Into<Option<AggregateOptions>>,
+    ) -> mongodb::error::Result<Cursor<bson::Document>> {
+        self.0.aggregate(pipeline, options).await
+    }
+
+    pub async fn count_documents(
+        &self,
+        filter: impl Into<Option<bson::Document>>,
+        options: impl Into<Option<CountOptions>>,
+    ) -> mongodb::error::Result<u64> {
+        self.0.count_documents(filter, options).await
+    }
+
+    pub async fn distinct(
+        &self,
+        field: impl AsRef<str>,
+        filter: impl Into<Option<bson::Document>>,
+        options: impl Into<Option<DistinctOptions>>,
+    ) -> mongodb::error::Result<Vec<Bson>> {
+        self.0.distinct(field, filter, options).await
+    }
+}
+
+impl<D: DeserializeOwned + Unpin + Send + Sync> Reader<D> {
+    pub async fn find(
+        &self,
+        filter: impl Into<Option<bson::Document>>,
+        options: impl Into<Option<FindOptions>>,
+    ) -> mongodb::error::Result<Cursor<D>> {
+        self.0.find(filter, options).await
+    }
+
+    pub async fn find_one(
+        &self,
+        filter: impl Into<Option<bson::Document>>,
+        options: impl Into<Option<FindOneOptions>>,
+    ) -> mongodb::error::Result<Option<D>> {
+        self.0.find_one(filter, options).await
+    }
+}
+
+/// What the standard fetchers read from: the driver collection of a writable collection, or the
+/// [`Reader`] of a read-only one.
+pub trait Source<D>: Send + Sync {
+    /// Reads of the stored documents, before they are decoded.
+    fn reader(&self) -> Reader<bson::Document>;
+}
+
+impl<D: Send + Sync> Source<D> for mongodb::Collection<D> {
+    fn reader(&self) -> Reader<bson::Document> {
+        Reader(self.clone_with_type())
+    }
+}
+
+impl<D: Send + Sync> Source<D> for Reader<D> {
+    fn reader(&self) -> Reader<bson::Document> {
+        self.clone_with_type()
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, HashMap};
+
+use mongodb::bson::{self, doc, oid::ObjectId, Bson, DateTime, Uuid};
+use mongodb::error::ErrorKind;
+use mongodb::options::{ValidationAction, ValidationLevel};
+use mongodb::Database;
+
+/// Mapping of a Rust type onto a `$jsonSchema` fragment. Derived by `Document` and `JsonSchema`.
+pub trait JsonSchema {
+    /// Whether a field of this type has to be present.
+    const REQUIRED: bool = true;
+
+    fn json_schema() -> bson::Document;
+}
+
+macro_rules! bson_type {
+    ($name:literal: $($ty:ty),*) => {
+        $(
+            impl JsonSchema for $ty {
+                fn json_schema() -> bson::Document {
+                    doc! { "bsonType": $name }
+                }
+            }
+        )*
+    };
+}
+
+bson_type!("string": String, str, char);
+bson_type!("bool": bool);
+bson_type!("int": i8, i16, i32, u8, u16);
+bson_type!("long": i64, u32, u64);
+bson_type!("double": f32, f64);
+bson_type!("objectId": ObjectId);
+bson_type!("date": DateTime);
+bson_type!("binData": Uuid);
+bson_type!("object": bson::Document);
+
+impl JsonSchema for Bson {
+    fn json_schema() -> bson::Document {
+        bson::Document::new()
+    }
+}
+
+impl<T: JsonSchema + ?Sized> JsonSchema for Box<T> {
+    const REQUIRED: bool = T::REQUIRED;
+
+    fn json_schema() -> bson::Document {
+        T::json_schema()
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Option<T> {
+    const REQUIRED: bool = false;
+
+    fn json_schema() -> bson::Document {
+        doc! { "anyOf": [T::json_schema(), { "bsonType": "null" }] }
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Vec<T> {
+    fn json_schema() -> bson::Document {
+        doc! { "bsonType": "array", "items": T::json_schema() }
+    }
+}
+
+impl<T: JsonSchema, S> JsonSchema for HashMap<String, T, S> {
+    fn json_schema() -> bson::Document {
+        doc! { "bsonType": "object", "additionalProperties": T::json_schema() }
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for BTreeMap<String, T> {
+    fn json_schema() -> bson::Document {
+        doc! { "bsonType": "object", "additionalProperties": T::json_schema() }
+    }
+}
+
+/// Installs the schema of `D` as the validator of the collection `name`, creating the collection
+/// if it does not exist yet.
+pub async fn apply<D: JsonSchema>(
+    db: &Database,
+    name: &str,
+    level: ValidationLevel,
+    action: ValidationAction,
+) -> mongodb::error::Result<()> {
+    let options = doc! {
+        "validator": { "$jsonSchema": D::json_schema() },
+        "validationLevel": bson::to_bson(&level)?,
+        "validationAction": bson::to_bson(&action)?,
+    };
+
+    let mut modify = doc! { "collMod": name };
+    modify.extend(options.clone());
+
+    match db.run_command(modify, None).await {
+        Err(e) if matches!(&*e.kind, ErrorKind::Command(c) if c.code == 26) => {
+            let mut create = doc! { "create": name };
+            create.extend(options);
+
+            db.run_command(create, None).await.map(drop)
+        }
+        res => res.map(drop),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::JsonSchema;
+
+    #[test]
+    fn it_maps_containers() {
+        const { assert!(!<Option<u16>>::REQUIRED) };
+        assert_eq!(
+            <Vec<Option<String>>>::json_schema(),
+            doc! { "bsonType": "array", "items": {
+                "anyOf": [{ "bsonType": "string" }, { "bsonType": "null" }]
+            } }
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! Incrementing numbers for fields declared with `#[coll(sequence = "name")]`.
+//!
+//! Values are allocated on insert through the ORM, and only for documents which leave the field
+//! out or `null`, so such fields are usually `Option<i64>`. Inserting many documents reserves a
+//! block of values with a single round trip.
+//!
+//! Sequences are kept in the [`Counters`] of the database unless the collection is wrapped with
+//! [`WritableCollection::with_sequences`]:
+//!
+//! ```ignore
+//! let users = UserColl::new(&db).with_sequences(MemorySequences::default());
+//! ```
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+use mongodb::bson::{self, doc, Bson};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+
+use crate::mongo::{ReadableCollection, Token, WritableCollection};
+use crate::{Collection, Document, Error, Fetcher};
+
+/// The collection [`Counters`] keeps sequences in by default.
+pub const COUNTERS: &str = "counters";
+
+/// Where sequences are kept.
+pub trait Sequences: Send + Sync {
+    /// Reserves the next `n` values of the sequence `name`, returning the first. Sequences start
+    /// at 1.
+    fn reserve(&self, name: &str, n: u64) -> impl Future<Output = Result<i64, Error>> + Send;
+}
+
+/// Sequences as documents `{ _id: name, value: last }`, bumped with `findOneAndUpdate`.
+#[derive(Clone)]
+pub struct Counters(mongodb::Collection<bson::Document>);
+
+impl Counters {
+    pub fn new(db: &mongodb::Database) -> Self {
+        Self(db.collection(COUNTERS))
+    }
+
+    pub fn with_collection(coll: mongodb::Collection<bson::Document>) -> Self {
+        Self(coll)
+    }
+}
+
+impl Sequences for Counters {
+    async fn reserve(&self, name: &str, n: u64) -> Result<i64, Error> {
+        let mut opts = FindOneAndUpdateOptions::default();
+        opts.upsert = Some(true);
+        opts.return_document = Some(ReturnDocument::After);
+
+        let counter = self
+            .0
+            .find_one_and_update(
+                doc! { "_id": name },
+                doc! { "$inc": { "value": n as i64 } },
+                opts,
+            )
+            .await?
+            .unwrap_or_default();
+
+        let last = match counter.get("value") {
+            Some(Bson::Int32(v)) => *v as i64,
+            Some(Bson::Int64(v)) => *v,
+            Some(Bson::Double(v)) => *v as i64,
+            _ => n as i64,
+        };
+        Ok(last - n as i64 + 1)
+    }
+}
+
+/// Sequences kept in memory, for tests.
+#[derive(Default)]
+pub struct MemorySequences(Mutex<HashMap<String, i64>>);
+
+impl MemorySequences {
+    /// The last value allocated from `name`.
+    pub fn last(&self, name: &str) -> i64 {
+        self.0.lock().unwrap().get(name).copied().unwrap_or(0)
+    }
+}
+
+impl Sequences for MemorySequences {
+    async fn reserve(&self, name: &str, n: u64) -> Result<i64, Error> {
+        let mut last = self.0.lock().unwrap();
+        let last = last.entry(name.to_owned()).or_default();
+
+        *last += n as i64;
+        Ok(*last - n as i64 + 1)
+    }
+}
+
+/// A collection whose sequence fields are numbered from `S`, see
+/// [`WritableCollection::with_sequences`]. Wrappers overriding writes, such as
+/// [`Cached`](crate::cache::Cached), go around it rather than inside it.
+#[derive(Clone)]
+pub struct Sequenced<C, S> {
+    coll: C,
+    seq: S,
+}
+
+impl<C, S> Sequenced<C, S> {
+    pub fn new(coll: C, seq: S) -> Self {
+        Self { coll, seq }
+    }
+
+    pub fn sequences(&self) -> &S {
+        &self.seq
+    }
+}
+
+impl<C: ReadableCollection, S: Send + Sync> Collection for Sequenced<C, S> {
+    type Internal = C::Internal;
+    type Document = C::Document;
+
+    fn fetch<F: Fetcher<Self::Document, Self::Internal>>(
+        &self,
+        f: F,
+    ) -> impl Future<Output = Result<F::Output, F::Error>> + Send {
+        self.coll.fetch(f)
+    }
+}
+
+impl<C: ReadableCollection, S: Send + Sync> ReadableCollection for Sequenced<C, S> {
+    fn inner(&self, token: Token) -> &mongodb::Collection<Self::Document> {
+        self.coll.inner(token)
+    }
+
+    fn database(&self, token: Token) -> &mongodb::Database {
+        self.coll.database(token)
+    }
+}
+
+impl<C: WritableCollection, S: Sequences> WritableCollection for Sequenced<C, S> {
+    fn sequences(&self) -> impl Sequences + '_ {
+        &self.seq
+    }
+
+    fn written(&self) {
+        self.coll.written()
+    }
+}
+
+impl<S: Sequences> Sequences for &S {
+    fn reserve(&self, name: &str, n: u64) -> impl Future<Output = Result<i64, Error>> + Send {
+        (**self).reserve(name, n)
+    }
+}
+
+/// Fills in the sequence fields of `D` the documents are missing, reserving one block per
+/// sequence.
+pub async fn assign<D: Document, S: Sequences>(
+    seq: &S,
+    mut docs: Vec<&mut bson::Document>,
+) -> Result<(), Error> {
+    for (field, name) in D::SEQUENCES {
+        let mut missing = docs
+            .iter_mut()
+            .filter(|doc| matches!(doc.get(field), None | Some(Bson::Null)))
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            continue;
+        }
+
+        let first = seq.reserve(name, missing.len() as u64).await?;
+        for (i, doc) in missing.iter_mut().enumerate() {
+            doc.insert(*field, first + i as i64);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use mongodb::bson::{doc, Bson};
+
+    use super::{assign, MemorySequences};
+    use crate::mongo::ReadOnly;
+    use crate::Document;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Invoice {}
+
+    impl Document for Invoice {
+        type Collection = ReadOnly<Self>;
+        type Id = crate::id::ObjectIdStrategy;
+        type Index = std::convert::Infallible;
+
+        const SEQUENCES: &'static [(&'static str, &'static str)] = &[("no", "doc_no")];
+    }
+
+    #[test]
+    fn it_allocates_blocks() {
+        let seq = MemorySequences::default();
+        let mut docs = [doc! { "no": Bson::Null }, doc! { "no": 7 }, doc! {}];
+
+        let run = assign::<Invoice, _>(&seq, docs.iter_mut().collect());
+        run.now_or_never().unwrap().unwrap();
+        assert_eq!(docs[0].get_i64("no"), Ok(1));
+        assert_eq!(docs[1].get_i32("no"), Ok(7));
+        assert_eq!(docs[2].get_i64("no"), Ok(2));
+
+        let mut next = doc! {};
+        let run = assign::<Invoice, _>(&seq, vec![&mut next]);
+        run.now_or_never().unwrap().unwrap();
+        assert_eq!(next.get_i64("no"), Ok(3));
+        assert_eq!(seq.last("doc_no"), 3);
+    }
+}
@@ -0,0 +1,134 @@
+//! Soft deletes, enabled with `#[coll(option(soft_delete = deleted_at))]`.
+//!
+//! Deleting such a document stamps `deleted_at` instead of removing it, and the standard fetchers
+//! skip stamped documents unless scoped otherwise with [`ScopedFetcher::with_deleted`] or
+//! [`ScopedFetcher::only_deleted`]. Indexes declared with `active_only` only cover documents which
+//! are not deleted.
+
+use std::future::Future;
+use std::marker::PhantomData;
+
+use mongodb::bson::{self, doc, Bson};
+
+use crate::explain::Query;
+use crate::mongo::Source;
+use crate::{Document, Error, Fetcher};
+
+/// Which documents a fetcher sees. Filters already mentioning the `deleted_at` field are left as
+/// they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scope {
+    #[default]
+    Active,
+    WithDeleted,
+    OnlyDeleted,
+}
+
+impl Scope {
+    pub fn apply<D: Document>(self, filter: &mut bson::Document) {
+        let Some(field) = D::SOFT_DELETE.filter(|field| !filter.contains_key(field)) else {
+            return;
+        };
+
+        match self {
+            Scope::Active => {
+                filter.insert(field, Bson::Null);
+            }
+            Scope::OnlyDeleted => {
+                filter.insert(field, doc! { "$ne": Bson::Null });
+            }
+            Scope::WithDeleted => {}
+        }
+    }
+}
+
+/// A fetcher which can be scoped, implemented by the standard fetchers.
+pub trait ScopedFetcher<D>: Sized {
+    type Output;
+
+    fn fetch_in<S: Source<D>>(
+        self,
+        surface: &S,
+        scope: Scope,
+    ) -> impl Future<Output = Result<Self::Output, Error>> + Send;
+
+    /// Also sees deleted documents.
+    fn with_deleted(self) -> Scoped<Self, D> {
+        Scoped(self, Scope::WithDeleted, PhantomData)
+    }
+
+    /// Only sees deleted documents.
+    fn only_deleted(self) -> Scoped<Self, D> {
+        Scoped(self, Scope::OnlyDeleted, PhantomData)
+    }
+}
+
+pub struct Scoped<F, D>(pub F, pub Scope, pub PhantomData<fn() -> D>);
+
+impl<D, F, S> Fetcher<D, S> for Scoped<F, D>
+where
+    S: Source<D>,
+    F: ScopedFetcher<D> + Fetcher<D, S>,
+{
+    type Output = <F as ScopedFetcher<D>>::Output;
+    type Error = Error;
+
+    fn fetch(self, surface: &S) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send {
+        self.0.fetch_in(surface, self.1)
+    }
+
+    fn query(&self) -> Option<Query> {
+        let query = self.0.query()?;
+        Some(Query {
+            scope: self.1,
+            ..query
+        })
+    }
+}
+
+/// The partial filter of `active_only` indexes.
+pub fn active_filter(field: &str) -> bson::Document {
+    doc! { field: Bson::Null }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{doc, Bson};
+
+    use super::Scope;
+    use crate::mongo::ReadOnly;
+    use crate::Document;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Post {}
+
+    impl Document for Post {
+        type Collection = ReadOnly<Self>;
+        type Id = crate::id::ObjectIdStrategy;
+        type Index = std::convert::Infallible;
+
+        const SOFT_DELETE: Option<&'static str> = Some("deleted");
+    }
+
+    #[test]
+    fn it_scopes_filters() {
+        let scoped = |scope: Scope, mut filter| {
+            scope.apply::<Post>(&mut filter);
+            filter
+        };
+
+        assert_eq!(
+            scoped(Scope::Active, doc! { "a": 1 }),
+            doc! { "a": 1, "deleted": null }
+        );
+        assert_eq!(
+            scoped(Scope::OnlyDeleted, doc! {}),
+            doc! { "deleted": { "$ne": Bson::Null } }
+        );
+        assert_eq!(scoped(Scope::WithDeleted, doc! { "a": 1 }), doc! { "a": 1 });
+        assert_eq!(
+            scoped(Scope::Active, doc! { "deleted": { "$lt": 5 } }),
+            doc! { "deleted": { "$lt": 5 } }
+        );
+    }
+}
@@ -0,0 +1,144 @@
+//! Fields declared with `#[coll(created_at)]` and `#[coll(updated_at)]`, kept up to date by the
+//! ORM's writes.
+//!
+//! Inserts fill in whichever of the two the document leaves out, replacements set `updated_at`,
+//! and updates add it to their operators. With `#[coll(option(server_timestamps))]` updates use
+//! `$currentDate`; inserts and replacements have no operators, so they always carry the client's
+//! time. Upserts become updates, so `created_at` is only set when the document is inserted.
+
+use mongodb::bson::{self, doc, Bson, DateTime};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timestamps {
+    /// Serialized name of the `#[coll(created_at)]` field.
+    pub created_at: Option<&'static str>,
+    /// Serialized name of the `#[coll(updated_at)]` field.
+    pub updated_at: Option<&'static str>,
+    /// Whether updates take the time from the server.
+    pub server: bool,
+}
+
+impl Timestamps {
+    pub const NONE: Self = Self {
+        created_at: None,
+        updated_at: None,
+        server: false,
+    };
+
+    pub(crate) fn on_insert(&self, raw: &mut bson::Document, now: DateTime) {
+        for field in self.created_at.iter().chain(&self.updated_at) {
+            if matches!(raw.get(field), None | Some(Bson::Null)) {
+                raw.insert(*field, now);
+            }
+        }
+    }
+
+    pub(crate) fn on_replace(&self, raw: &mut bson::Document, now: DateTime) {
+        if let Some(field) = self.updated_at {
+            raw.insert(field, now);
+        }
+    }
+
+    pub(crate) fn on_update(&self, update: &mut bson::Document, now: DateTime) {
+        let Some(field) = self.updated_at else {
+            return;
+        };
+
+        if self.server {
+            operator(update, "$currentDate", field, true);
+        } else {
+            operator(update, "$set", field, now);
+        }
+    }
+
+    /// Turns the replacement `raw` into the update upserting it, which only sets `created_at` on
+    /// insert. Returns `raw` as is if there are no timestamps.
+    pub(crate) fn upsert(&self, mut raw: bson::Document, now: DateTime) -> bson::Document {
+        if *self == Self::NONE {
+            return raw;
+        }
+
+        raw.remove("_id");
+        let mut update = doc! {};
+        if let Some(field) = self.created_at {
+            let created = raw.remove(field).filter(|v| *v != Bson::Null);
+            operator(
+                &mut update,
+                "$setOnInsert",
+                field,
+                created.unwrap_or(now.into()),
+            );
+        }
+        if let Some(field) = self.updated_at {
+            raw.remove(field);
+        }
+        if !raw.is_empty() {
+            update.insert("$set", raw);
+        }
+
+        self.on_update(&mut update, now);
+        update
+    }
+}
+
+/// Adds `field: value` to the `op` operator of `update`.
+fn operator(update: &mut bson::Document, op: &str, field: &str, value: impl Into<Bson>) {
+    match update.get_document_mut(op) {
+        Ok(fields) => {
+            fields.insert(field, value);
+        }
+        Err(_) => {
+            update.insert(op, doc! { field: value.into() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{doc, DateTime};
+
+    use super::Timestamps;
+
+    #[test]
+    fn it_stamps_writes() {
+        let now = DateTime::from_millis(1_000);
+        let then = DateTime::from_millis(1);
+        let ts = Timestamps {
+            created_at: Some("created"),
+            updated_at: Some("updated"),
+            server: false,
+        };
+
+        let mut raw = doc! { "_id": 1, "created": then };
+        ts.on_insert(&mut raw, now);
+        assert_eq!(raw, doc! { "_id": 1, "created": then, "updated": now });
+
+        let mut update = doc! { "$set": { "a": 1 } };
+        ts.on_update(&mut update, now);
+        assert_eq!(update, doc! { "$set": { "a": 1, "updated": now } });
+
+        let upsert = ts.upsert(
+            doc! { "_id": 1, "a": 1, "created": null, "updated": then },
+            now,
+        );
+        assert_eq!(
+            upsert,
+            doc! {
+                "$setOnInsert": { "created": now },
+                "$set": { "a": 1, "updated": now },
+            }
+        );
+
+        let server = Timestamps { server: true, ..ts };
+        let mut update = doc! { "$inc": { "n": 1 } };
+        server.on_update(&mut update, now);
+        assert_eq!(
+            update,
+            doc! { "$inc": { "n": 1 }, "$currentDate": { "updated": true } }
+        );
+        assert_eq!(
+            Timestamps::NONE.upsert(doc! { "_id": 1 }, now),
+            doc! { "_id": 1 }
+        );
+    }
+}
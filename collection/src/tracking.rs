@@ -0,0 +1,273 @@
+//! Dirty tracking, saving only the fields of a document which changed since it was fetched.
+//!
+//! ```ignore
+//! let mut user = users.fetch(ById(id).tracked()).await?.unwrap();
+//! user.name = "Ada".into();
+//! users.save(&mut user).await?; // { "$set": { "name": "Ada" } }
+//! ```
+//!
+//! Changes are found by comparing the encoded documents, so serde renames apply and nested
+//! documents are diffed down to their fields. Arrays which changed are set as a whole, as are
+//! nested documents with keys containing `.` or starting with `$`. Such keys at the top level fail
+//! the save with [`Error::Unaddressable`]. Documents fetched at an older version, and migrated on
+//! the way, are saved whole.
+
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use futures_util::FutureExt;
+use mongodb::bson::{self, doc, Bson};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::explain::Query;
+use crate::{concurrency, migrate, Document, Error, Fetcher};
+
+/// A document along with the encoding it was fetched or last saved with.
+#[derive(Debug, Clone)]
+pub struct Tracked<D> {
+    doc: D,
+    original: bson::Document,
+}
+
+impl<D: Document + Serialize> Tracked<D> {
+    pub fn new(doc: D) -> Result<Self, Error> {
+        let original = migrate::encode(&doc)?;
+        Ok(Self { doc, original })
+    }
+
+    pub fn original(&self) -> &bson::Document {
+        &self.original
+    }
+
+    /// The update bringing the stored document up to date. The `_id`, the version and
+    /// `updated_at` are left to the ORM.
+    pub fn changes(&self) -> Result<bson::Document, Error> {
+        let mut update = diff(&self.original, &migrate::encode(&self.doc)?)?;
+
+        let managed = [Some("_id"), D::VERSION_FIELD, D::TIMESTAMPS.updated_at];
+        for op in ["$set", "$unset"] {
+            if let Ok(fields) = update.get_document_mut(op) {
+                for field in managed.iter().flatten() {
+                    fields.remove(field);
+                }
+                if fields.is_empty() {
+                    update.remove(op);
+                }
+            }
+        }
+        Ok(update)
+    }
+
+    pub fn is_dirty(&self) -> Result<bool, Error> {
+        Ok(!self.changes()?.is_empty())
+    }
+
+    pub fn into_inner(self) -> D {
+        self.doc
+    }
+}
+
+impl<D: Document + Serialize + DeserializeOwned> Tracked<D> {
+    /// Takes the saved document as the new original, at the version the save moved it to.
+    pub(crate) fn saved(&mut self) -> Result<(), Error> {
+        let mut raw = migrate::encode(&self.doc)?;
+        concurrency::bump::<D>(&mut raw);
+
+        self.doc = migrate::decode(raw.clone())?;
+        self.original = raw;
+        Ok(())
+    }
+}
+
+impl<D> Deref for Tracked<D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        &self.doc
+    }
+}
+
+impl<D> DerefMut for Tracked<D> {
+    fn deref_mut(&mut self) -> &mut D {
+        &mut self.doc
+    }
+}
+
+/// The `$set` and `$unset` turning `before` into `after`, failing if a changed top-level field
+/// has a key which can't be used as a path.
+pub fn diff(before: &bson::Document, after: &bson::Document) -> Result<bson::Document, Error> {
+    let changed = after
+        .iter()
+        .filter(|(key, value)| before.get(key) != Some(value))
+        .map(|(key, _)| key)
+        .chain(before.keys().filter(|key| !after.contains_key(key)));
+    for key in changed {
+        if !addressable_key(key) {
+            return Err(Error::Unaddressable(key.clone()));
+        }
+    }
+
+    let mut set = doc! {};
+    let mut unset = doc! {};
+    diff_fields("", before, after, &mut set, &mut unset);
+
+    let mut update = doc! {};
+    if !set.is_empty() {
+        update.insert("$set", set);
+    }
+    if !unset.is_empty() {
+        update.insert("$unset", unset);
+    }
+    Ok(update)
+}
+
+/// Whether the fields of `doc` can be updated one by one through dotted paths.
+fn addressable(doc: &bson::Document) -> bool {
+    doc.keys().all(|k| addressable_key(k))
+}
+
+fn addressable_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains('.') && !key.starts_with('$')
+}
+
+fn diff_fields(
+    prefix: &str,
+    before: &bson::Document,
+    after: &bson::Document,
+    set: &mut bson::Document,
+    unset: &mut bson::Document,
+) {
+    let path = |key: &str| {
+        if prefix.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{}.{}", prefix, key)
+        }
+    };
+
+    for (key, value) in after {
+        match (before.get(key), value) {
+            (Some(old), _) if old == value => {}
+            (Some(Bson::Document(old)), Bson::Document(new))
+                if addressable(old) && addressable(new) =>
+            {
+                diff_fields(&path(key), old, new, set, unset)
+            }
+            _ => {
+                set.insert(path(key), value.clone());
+            }
+        }
+    }
+
+    for key in before.keys().filter(|key| !after.contains_key(key)) {
+        unset.insert(path(key), "");
+    }
+}
+
+/// Outputs of fetchers which can be tracked, see [`Fetcher::tracked`].
+pub trait Trackable<D> {
+    type Tracked;
+
+    fn track(self) -> Result<Self::Tracked, Error>;
+}
+
+impl<D: Document + Serialize> Trackable<D> for D {
+    type Tracked = Tracked<D>;
+
+    fn track(self) -> Result<Tracked<D>, Error> {
+        Tracked::new(self)
+    }
+}
+
+impl<D: Document + Serialize> Trackable<D> for Option<D> {
+    type Tracked = Option<Tracked<D>>;
+
+    fn track(self) -> Result<Option<Tracked<D>>, Error> {
+        self.map(Tracked::new).transpose()
+    }
+}
+
+impl<D: Document + Serialize> Trackable<D> for Vec<D> {
+    type Tracked = Vec<Tracked<D>>;
+
+    fn track(self) -> Result<Vec<Tracked<D>>, Error> {
+        self.into_iter().map(Tracked::new).collect()
+    }
+}
+
+/// See [`Fetcher::tracked`].
+pub struct Track<A, Doc>(pub(crate) A, pub(crate) PhantomData<fn() -> Doc>);
+
+impl<Doc, I, A> Fetcher<Doc, I> for Track<A, Doc>
+where
+    A: Fetcher<Doc, I>,
+    A::Output: Trackable<Doc>,
+    A::Error: From<Error>,
+{
+    type Output = <A::Output as Trackable<Doc>>::Tracked;
+    type Error = A::Error;
+
+    fn fetch(self, surface: &I) -> impl Future<Output = Result<Self::Output, A::Error>> + Send {
+        self.0
+            .fetch(surface)
+            .map(|res| res.and_then(|out| Ok(out.track()?)))
+    }
+
+    fn query(&self) -> Option<Query> {
+        self.0.query()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::diff;
+    use crate::Error;
+
+    #[test]
+    fn it_diffs_documents() {
+        let before = doc! {
+            "_id": 1,
+            "name": "a",
+            "address": { "city": "x", "zip": "1" },
+            "tags": ["a"],
+            "meta": { "a.b": 1 },
+            "gone": true,
+        };
+        let after = doc! {
+            "_id": 1,
+            "name": "a",
+            "address": { "city": "y", "zip": "1", "street": "s" },
+            "tags": ["a", "b"],
+            "meta": { "a.b": 2 },
+        };
+
+        assert_eq!(
+            diff(&before, &after).unwrap(),
+            doc! {
+                "$set": {
+                    "address.city": "y",
+                    "address.street": "s",
+                    "tags": ["a", "b"],
+                    "meta": { "a.b": 2 },
+                },
+                "$unset": { "gone": "" },
+            }
+        );
+        assert_eq!(diff(&after, &after).unwrap(), doc! {});
+
+        let dotted = doc! { "_id": 1, "a.b": 1, "$c": 1 };
+        assert_eq!(diff(&dotted, &dotted).unwrap(), doc! {});
+        assert!(matches!(
+            diff(&dotted, &doc! { "_id": 1, "a.b": 2, "$c": 1 }),
+            Err(Error::Unaddressable(key)) if key == "a.b"
+        ));
+        assert!(matches!(
+            diff(&dotted, &doc! { "_id": 1, "a.b": 1 }),
+            Err(Error::Unaddressable(key)) if key == "$c"
+        ));
+    }
+}
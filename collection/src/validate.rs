@@ -0,0 +1,244 @@
+//! Field rules declared with `#[coll(validate(...))]`, checked before the ORM writes a document.
+//!
+//! ```ignore
+//! #[coll(validate(length(1..=64), email))]
+//! email: String,
+//! #[coll(validate(range(0..=120)))]
+//! age: Option<u8>,
+//! #[coll(validate(regex = "^[a-z]+$"))]
+//! handle: String,
+//! #[coll(validate(nested))]
+//! address: Address,
+//! ```
+//!
+//! Inserts, replacements, upserts and saves fail with [`Error::Invalid`] listing every violation;
+//! documents are checked as the `before_` hooks leave them. Updates by operators are not checked.
+//! Rules skip fields which are `None`. With `#[coll(option(mirror_validation))]` the rules are
+//! also added to the derived `$jsonSchema`.
+//!
+//! [`Error::Invalid`]: crate::Error::Invalid
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::OnceLock;
+
+pub use regex::Regex;
+
+/// The pattern of `email`, also used in the `$jsonSchema`.
+pub const EMAIL: &str = r"^[^@\s]+@[^@\s]+\.[^@\s]+$";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    /// Dotted path of the field, by serialized names and array indexes.
+    pub path: String,
+    pub rule: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violations(pub Vec<Violation>);
+
+impl fmt::Display for Violations {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, v) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}: {}", v.path, v.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Violations {}
+
+/// Derived by `Document` and `Validate` from the rules of each field.
+pub trait Validate {
+    /// Adds the violations of `self` to `out`, with paths below `path`.
+    fn violations(&self, path: &str, out: &mut Vec<Violation>);
+
+    fn validate(&self) -> Result<(), Violations> {
+        let mut out = Vec::new();
+        self.violations("", &mut out);
+
+        if out.is_empty() {
+            Ok(())
+        } else {
+            Err(Violations(out))
+        }
+    }
+}
+
+impl<T: Validate + ?Sized> Validate for Box<T> {
+    fn violations(&self, path: &str, out: &mut Vec<Violation>) {
+        (**self).violations(path, out)
+    }
+}
+
+impl<T: Validate> Validate for Option<T> {
+    fn violations(&self, path: &str, out: &mut Vec<Violation>) {
+        if let Some(inner) = self {
+            inner.violations(path, out)
+        }
+    }
+}
+
+impl<T: Validate> Validate for Vec<T> {
+    fn violations(&self, path: &str, out: &mut Vec<Violation>) {
+        for (i, item) in self.iter().enumerate() {
+            item.violations(&join(path, &i.to_string()), out)
+        }
+    }
+}
+
+pub fn join(path: &str, field: &str) -> String {
+    if path.is_empty() {
+        field.to_owned()
+    } else {
+        format!("{}.{}", path, field)
+    }
+}
+
+pub fn is_email(s: &str) -> bool {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(EMAIL).unwrap()).is_match(s)
+}
+
+/// Values `length` applies to: characters of strings, items of collections.
+pub trait Length {
+    fn length(&self) -> Option<usize>;
+}
+
+impl Length for str {
+    fn length(&self) -> Option<usize> {
+        Some(self.chars().count())
+    }
+}
+
+impl Length for String {
+    fn length(&self) -> Option<usize> {
+        self.as_str().length()
+    }
+}
+
+impl<T> Length for Vec<T> {
+    fn length(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<K, V, S> Length for HashMap<K, V, S> {
+    fn length(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<K, V> Length for BTreeMap<K, V> {
+    fn length(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<T: Length + ?Sized> Length for Box<T> {
+    fn length(&self) -> Option<usize> {
+        (**self).length()
+    }
+}
+
+impl<T: Length> Length for Option<T> {
+    fn length(&self) -> Option<usize> {
+        self.as_ref()?.length()
+    }
+}
+
+/// Values `email` and `regex` apply to.
+pub trait Text {
+    fn text(&self) -> Option<&str>;
+}
+
+impl Text for str {
+    fn text(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl Text for String {
+    fn text(&self) -> Option<&str> {
+        Some(self)
+    }
+}
+
+impl<T: Text + ?Sized> Text for Box<T> {
+    fn text(&self) -> Option<&str> {
+        (**self).text()
+    }
+}
+
+impl<T: Text> Text for Option<T> {
+    fn text(&self) -> Option<&str> {
+        self.as_ref()?.text()
+    }
+}
+
+/// Values `range` applies to.
+pub trait Scalar {
+    type Value;
+
+    fn value(&self) -> Option<&Self::Value>;
+}
+
+macro_rules! scalar {
+    ($($t:ty),*) => {
+        $(impl Scalar for $t {
+            type Value = $t;
+
+            fn value(&self) -> Option<&$t> {
+                Some(self)
+            }
+        })*
+    };
+}
+
+scalar!(i8, i16, i32, i64, u8, u16, u32, u64, usize, f32, f64);
+
+impl<T: Scalar> Scalar for Option<T> {
+    type Value = T::Value;
+
+    fn value(&self) -> Option<&T::Value> {
+        self.as_ref()?.value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_email, join, Length, Scalar, Validate, Violation};
+
+    struct Tag(String);
+
+    impl Validate for Tag {
+        fn violations(&self, path: &str, out: &mut Vec<Violation>) {
+            if !(1..=3).contains(&self.0.length().unwrap()) {
+                out.push(Violation {
+                    path: path.to_owned(),
+                    rule: "length",
+                    message: "length must be in 1..=3".to_owned(),
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn it_collects_violations() {
+        let tags = vec![Tag("ok".into()), Tag("long".into()), Tag(String::new())];
+        let mut out = Vec::new();
+        tags.violations("tags", &mut out);
+        let paths = out.iter().map(|v| v.path.as_str()).collect::<Vec<_>>();
+        assert_eq!(paths, ["tags.1", "tags.2"]);
+
+        assert_eq!(join("", "a"), "a");
+        assert_eq!("é".length(), Some(1));
+        assert_eq!(None::<u8>.value(), None);
+        assert!(is_email("a@b.co") && !is_email("a@b") && !is_email("a b@c.d"));
+        assert!(Some(Tag("ok".into())).validate().is_ok());
+    }
+}
@@ -0,0 +1,353 @@
+//! Typed change streams over the collection of a document, opened with the generated `watch`.
+//!
+//! Inserted and replaced documents are decoded like fetched ones, migrated to the current version
+//! with their `after_load` hook run. Updates only carry the changed fields, as stored.
+//!
+//! ```ignore
+//! let filter = ChangeFilter::new().operation(OperationType::Insert);
+//! let mut events = UserColl::new(&db).watch(filter, store).await?;
+//!
+//! while let Some(ev) = events.try_next().await? {
+//!     handle(ev).await;
+//!     events.checkpoint().await?;
+//! }
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::future::BoxFuture;
+use futures_util::Stream;
+use mongodb::bson::{self, doc, to_bson, Bson, Document};
+use mongodb::change_stream::event::{ChangeStreamEvent, OperationType, ResumeToken};
+use mongodb::change_stream::ChangeStream;
+use mongodb::options::{ChangeStreamOptions, FullDocumentType, UpdateOptions};
+use serde::de::DeserializeOwned;
+
+use crate::fetchers;
+use crate::id::Id;
+use crate::Error;
+
+#[derive(Debug, Clone)]
+pub enum ChangeEvent<T: crate::Document> {
+    Insert(T),
+    Update {
+        id: Id<T>,
+        updated_fields: Document,
+        removed_fields: Vec<String>,
+    },
+    Replace(T),
+    Delete(Id<T>),
+    /// The stream was closed by the server, e.g. because the collection was dropped or renamed.
+    Invalidate,
+}
+
+impl<T> ChangeEvent<T>
+where
+    T: crate::Document + DeserializeOwned + Send,
+{
+    async fn from_event(ev: ChangeStreamEvent<Document>) -> Result<Option<Self>, Error> {
+        let id = || -> Result<Id<T>, Error> {
+            let id = ev
+                .document_key
+                .as_ref()
+                .and_then(|key| key.get("_id"))
+                .cloned()
+                .unwrap_or(Bson::Null);
+            Ok(bson::from_bson(id)?)
+        };
+
+        Ok(Some(match ev.operation_type {
+            OperationType::Insert | OperationType::Replace => {
+                let Some(raw) = ev.full_document else {
+                    return Ok(None);
+                };
+                let doc = fetchers::load(raw).await?;
+
+                if ev.operation_type == OperationType::Insert {
+                    Self::Insert(doc)
+                } else {
+                    Self::Replace(doc)
+                }
+            }
+            OperationType::Delete => Self::Delete(id()?),
+            OperationType::Update => {
+                let id = id()?;
+                let Some(desc) = ev.update_description else {
+                    return Ok(None);
+                };
+
+                Self::Update {
+                    id,
+                    updated_fields: desc.updated_fields,
+                    removed_fields: desc.removed_fields,
+                }
+            }
+            OperationType::Invalidate => Self::Invalidate,
+            // Drops and renames are always followed by an invalidate
+            _ => return Ok(None),
+        }))
+    }
+}
+
+/// Server side filter applied to a change stream.
+#[derive(Debug, Default, Clone)]
+pub struct ChangeFilter {
+    operations: Vec<OperationType>,
+    full_document: Document,
+}
+
+impl ChangeFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only yield events of the given operation. May be called repeatedly.
+    pub fn operation(mut self, op: OperationType) -> Self {
+        self.operations.push(op);
+        self
+    }
+
+    /// Only yield events whose document matches `filter`. Updates are looked up so the filter
+    /// applies to the post-image.
+    ///
+    /// Delete events carry no document, so they never match and are dropped from the stream.
+    pub fn full_document(mut self, filter: Document) -> Self {
+        for (k, v) in filter {
+            self.full_document.insert(format!("fullDocument.{}", k), v);
+        }
+        self
+    }
+
+    fn into_parts(self) -> (Vec<Document>, Option<FullDocumentType>) {
+        let lookup = (!self.full_document.is_empty()).then_some(FullDocumentType::UpdateLookup);
+
+        let mut matching = self.full_document;
+        if !self.operations.is_empty() {
+            let ops = self
+                .operations
+                .iter()
+                .map(|op| to_bson(op).expect("Operation types serialize"))
+                .collect::<Vec<_>>();
+            matching.insert("operationType", doc! { "$in": ops });
+        }
+
+        let pipeline = if matching.is_empty() {
+            Vec::new()
+        } else {
+            vec![doc! { "$match": matching }]
+        };
+
+        (pipeline, lookup)
+    }
+}
+
+/// Persistence for change stream resume tokens, allowing a consumer to continue where it left off.
+pub trait ResumeTokenStore {
+    type Error: From<Error>;
+
+    fn load(&self) -> impl Future<Output = Result<Option<ResumeToken>, Self::Error>> + Send;
+    fn save(&self, token: &ResumeToken) -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
+
+/// Never resumes.
+impl ResumeTokenStore for () {
+    type Error = Error;
+
+    async fn load(&self) -> Result<Option<ResumeToken>, Self::Error> {
+        Ok(None)
+    }
+    async fn save(&self, _: &ResumeToken) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Stores tokens as `{ _id: key, token }` in a MongoDB collection.
+#[derive(Debug, Clone)]
+pub struct CollectionTokenStore {
+    pub coll: mongodb::Collection<Document>,
+    pub key: String,
+}
+
+impl ResumeTokenStore for CollectionTokenStore {
+    type Error = Error;
+
+    async fn load(&self) -> Result<Option<ResumeToken>, Self::Error> {
+        let Some(entry) = self.coll.find_one(doc! { "_id": &self.key }, None).await? else {
+            return Ok(None);
+        };
+
+        Ok(match entry.get("token") {
+            Some(token) => Some(mongodb::bson::from_bson(token.clone())?),
+            None => None,
+        })
+    }
+
+    async fn save(&self, token: &ResumeToken) -> Result<(), Self::Error> {
+        let mut opts = UpdateOptions::default();
+        opts.upsert = Some(true);
+
+        self.coll
+            .update_one(
+                doc! { "_id": &self.key },
+                doc! { "$set": { "token": to_bson(token)? } },
+                opts,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+type Decoding<T> = BoxFuture<'static, Result<Option<ChangeEvent<T>>, Error>>;
+
+/// Typed change stream over a collection.
+///
+/// Tokens are only persisted on [`ChangeEvents::checkpoint`], so an event that has not been
+/// checkpointed is redelivered after a restart.
+pub struct ChangeEvents<T, S = ()>
+where
+    T: crate::Document,
+{
+    stream: ChangeStream<ChangeStreamEvent<Document>>,
+    decoding: Option<Decoding<T>>,
+    store: S,
+}
+
+impl<T, S> ChangeEvents<T, S>
+where
+    T: crate::Document + DeserializeOwned + Send + 'static,
+    S: ResumeTokenStore,
+{
+    pub async fn open(
+        coll: &mongodb::Collection<T>,
+        filter: ChangeFilter,
+        store: S,
+    ) -> Result<Self, S::Error> {
+        let (pipeline, lookup) = filter.into_parts();
+
+        let mut opts = ChangeStreamOptions::default();
+        opts.full_document = lookup;
+        opts.resume_after = store.load().await?;
+
+        let stream = coll
+            .clone_with_type::<Document>()
+            .watch(pipeline, opts)
+            .await
+            .map_err(Error::from)?;
+
+        Ok(Self {
+            stream,
+            decoding: None,
+            store,
+        })
+    }
+
+    pub fn resume_token(&self) -> Option<ResumeToken> {
+        self.stream.resume_token()
+    }
+
+    /// Persists the position after the most recently yielded event.
+    pub async fn checkpoint(&self) -> Result<(), S::Error> {
+        match self.stream.resume_token() {
+            Some(token) => self.store.save(&token).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T, S> Stream for ChangeEvents<T, S>
+where
+    T: crate::Document + DeserializeOwned + Send + 'static,
+    S: ResumeTokenStore + Unpin,
+{
+    type Item = Result<ChangeEvent<T>, S::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        loop {
+            if let Some(decoding) = &mut this.decoding {
+                let decoded = std::task::ready!(decoding.as_mut().poll(cx));
+                this.decoding = None;
+
+                match decoded {
+                    Ok(Some(ev)) => return Poll::Ready(Some(Ok(ev))),
+                    Ok(None) => {}
+                    Err(e) => return Poll::Ready(Some(Err(e.into()))),
+                }
+            }
+
+            match std::task::ready!(Pin::new(&mut this.stream).poll_next(cx)) {
+                Some(Ok(ev)) => this.decoding = Some(Box::pin(ChangeEvent::from_event(ev))),
+                Some(Err(e)) => return Poll::Ready(Some(Err(Error::from(e).into()))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+    use mongodb::bson::{self, doc, oid::ObjectId};
+    use mongodb::change_stream::event::OperationType;
+
+    use super::{ChangeEvent, ChangeFilter};
+    use crate::id::Id;
+    use crate::mongo::ReadOnly;
+    use crate::Document;
+
+    #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+    struct User {
+        email: String,
+    }
+
+    impl Document for User {
+        type Collection = ReadOnly<Self>;
+        type Id = crate::id::ObjectIdStrategy;
+        type Index = std::convert::Infallible;
+    }
+
+    fn decode(event: bson::Document) -> ChangeEvent<User> {
+        let ev = bson::from_document(event).unwrap();
+        let run = ChangeEvent::from_event(ev);
+        run.now_or_never().unwrap().unwrap().unwrap()
+    }
+
+    #[test]
+    fn it_builds_match_stage() {
+        let (pipeline, lookup) = ChangeFilter::new()
+            .operation(OperationType::Insert)
+            .operation(OperationType::Update)
+            .full_document(doc! { "email": "a@b.c" })
+            .into_parts();
+
+        assert!(lookup.is_some());
+        assert_eq!(
+            pipeline,
+            vec![doc! { "$match": {
+                "fullDocument.email": "a@b.c",
+                "operationType": { "$in": ["insert", "update"] },
+            } }]
+        );
+    }
+
+    #[test]
+    fn it_decodes_events() {
+        let id = ObjectId::new();
+        let key = doc! { "_id": id };
+        let token = doc! { "_data": "00" };
+
+        let ev = decode(doc! {
+            "_id": &token,
+            "operationType": "insert",
+            "documentKey": &key,
+            "fullDocument": { "_id": id, "email": "a@b.c" },
+        });
+        assert!(matches!(ev, ChangeEvent::Insert(User { email }) if email == "a@b.c"));
+
+        let ev = decode(doc! { "_id": &token, "operationType": "delete", "documentKey": &key });
+        assert!(matches!(ev, ChangeEvent::Delete(deleted) if deleted == Id::from_key(id)));
+    }
+}
@@ -1,4 +1,3 @@
-#![feature(iter_intersperse)]
 use std::collections::HashMap;
 
 use proc_macro::{Span, TokenStream as Ts1};
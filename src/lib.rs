@@ -1,20 +1,13 @@
-#![feature(async_fn_in_trait)]
-
 pub mod mongo {
-    pub mod err {
-        use thiserror::Error;
+    use std::future::Future;
 
-        #[derive(Debug, Error)]
-        pub enum IndexCreationError {}
+    pub mod err {
+        pub use collection::error::{IndexCreationError, WriteError};
     }
 
     pub trait MultiplexedGlobalSharable {
-        async fn prepare();
+        fn prepare() -> impl Future<Output = ()> + Send;
     }
 
-    pub trait ReadableCollection {
-        async fn ensure_indicies(&self) -> Result<(), err::IndexCreationError> {
-            Ok(())
-        }
-    }
+    pub use collection::mongo::{ReadOnly, ReadableCollection, WritableCollection};
 }
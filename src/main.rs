@@ -1,4 +1,3 @@
-#![feature(async_fn_in_trait)]
 mod simple {
     use profile::profile;
     #[profile(Copyable)]
@@ -86,8 +85,7 @@ mod transforming_unnamed {
 }
 
 mod collection {
-    use collection::Document;
-    use mongodb::bson::oid::ObjectId;
+    use collection::{Document, Id};
     use serde::Deserialize;
     use serde::Serialize;
 
@@ -97,7 +95,8 @@ mod collection {
     #[coll(option(collection_sharing))]
     struct User {
         #[serde(rename = "_id")]
-        id: ObjectId,
+        #[coll(id)]
+        id: Id<User>,
 
         #[coll(index(single email, unique, type=Text))]
         email: String,
@@ -119,4 +118,275 @@ mod collection {
     }
 }
 
+mod schema {
+    use collection::{Document, JsonSchema};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Document)]
+    #[coll(PostColl posts)]
+    #[serde(rename_all = "camelCase")]
+    struct Post {
+        #[serde(rename = "_id")]
+        id: i64,
+        title: String,
+        published_at: Option<i64>,
+        tags: Vec<String>,
+        state: State,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    enum State {
+        Draft,
+        #[serde(rename = "live")]
+        Published,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema)]
+    enum Gone {
+        #[serde(skip)]
+        Never,
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use collection::schema::JsonSchema;
+        use mongodb::bson::doc;
+
+        use super::*;
+
+        #[test]
+        fn it_derives_schemas() {
+            let schema = Post::json_schema();
+            let props = schema.get_document("properties").unwrap();
+
+            assert_eq!(schema.get_str("bsonType"), Ok("object"));
+            assert_eq!(
+                props.keys().collect::<Vec<_>>(),
+                ["_id", "title", "publishedAt", "tags", "state"]
+            );
+            assert_eq!(
+                props.get_document("state").unwrap(),
+                &doc! { "enum": ["Draft", "live"] }
+            );
+            assert_eq!(Gone::json_schema(), doc! { "not": {} });
+        }
+    }
+}
+
+mod validation {
+    use collection::{Document, JsonSchema, Validate};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Document)]
+    #[coll(MemberColl members)]
+    #[coll(option(mirror_validation))]
+    struct Member {
+        #[serde(rename = "_id")]
+        id: i64,
+        #[coll(validate(length(1..=64), email))]
+        email: String,
+        #[coll(validate(range(0..=120)))]
+        age: Option<u8>,
+        #[serde(rename = "nick")]
+        #[coll(validate(regex = "^[a-z]+$"))]
+        handle: String,
+        #[coll(validate(nested))]
+        address: Address,
+    }
+
+    #[derive(Serialize, Deserialize, JsonSchema, Validate)]
+    struct Address {
+        #[coll(validate(length(1..)))]
+        city: String,
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use collection::schema::JsonSchema;
+        use collection::validate::Validate;
+
+        use super::*;
+
+        fn member() -> Member {
+            Member {
+                id: 1,
+                email: "ada@example.com".into(),
+                age: None,
+                handle: "ada".into(),
+                address: Address {
+                    city: "London".into(),
+                },
+            }
+        }
+
+        #[test]
+        fn it_derives_validation() {
+            assert_eq!(member().validate(), Ok(()));
+
+            let invalid = Member {
+                email: "ada".into(),
+                age: Some(200),
+                handle: "Ada".into(),
+                address: Address { city: "".into() },
+                ..member()
+            };
+            let violations = invalid.validate().unwrap_err().0;
+            let found = violations
+                .iter()
+                .map(|v| (v.path.as_str(), v.rule))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                found,
+                [
+                    ("email", "email"),
+                    ("age", "range"),
+                    ("nick", "regex"),
+                    ("address.city", "length"),
+                ]
+            );
+
+            let schema = Member::json_schema();
+            let email = schema
+                .get_document("properties")
+                .unwrap()
+                .get_document("email");
+            assert_eq!(email.unwrap().get_i64("maxLength"), Ok(64));
+        }
+    }
+}
+
+mod finders {
+    use collection::Document;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Document)]
+    #[coll(AccountColl accounts)]
+    #[coll(index(compound org_handle, unique))]
+    #[coll(option(active_record))]
+    struct Account {
+        #[serde(rename = "_id")]
+        id: i64,
+        #[serde(rename = "mail")]
+        #[coll(index(single email, unique))]
+        email: String,
+        #[coll(index(single age))]
+        age: u32,
+        #[coll(index(compound org_handle))]
+        org: String,
+        #[coll(index(compound org_handle))]
+        handle: String,
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::future::{self, Future};
+        use std::pin::pin;
+        use std::sync::Mutex;
+        use std::task::{Context, Waker};
+
+        use collection::explain::Query;
+        use collection::index::Index;
+        use collection::{Collection, Fetcher};
+        use mongodb::bson::doc;
+        use mongodb::options::{ClientOptions, ReadPreference, SelectionCriteria, ServerAddress};
+
+        use super::*;
+
+        fn query<F: Fetcher<Account, mongodb::Collection<Account>>>(f: F) -> Query {
+            f.query().unwrap()
+        }
+
+        #[test]
+        fn it_derives_finders() {
+            let q = query(AccountColl::by_email("ada@example.com"));
+            assert_eq!(q.filter, doc! { "mail": "ada@example.com" });
+            assert_eq!(q.limit, Some(1));
+
+            let q = query(AccountColl::by_age(&36));
+            assert_eq!(q.filter, doc! { "age": 36_i64 });
+            assert_eq!(q.limit, None);
+
+            let q = query(AccountColl::by_org_handle("acme", "ada"));
+            assert_eq!(q.filter, doc! { "org": "acme", "handle": "ada" });
+            assert_eq!(q.limit, Some(1));
+
+            let q = query(AccountColl::by_org("acme"));
+            assert_eq!(q.filter, doc! { "org": "acme" });
+            assert_eq!(q.limit, None);
+        }
+
+        #[test]
+        fn it_looks_up_indexes_by_name() {
+            let from_name = <AccountIndex as Index>::from_name;
+
+            assert_eq!(from_name("email"), Some(AccountIndex::Email));
+            assert_eq!(from_name("mail_1"), Some(AccountIndex::Email));
+            assert_eq!(from_name("org_handle"), Some(AccountIndex::OrgHandle));
+            assert_eq!(from_name("org_1_handle_1"), Some(AccountIndex::OrgHandle));
+            assert_eq!(from_name("_id_"), None);
+            assert_eq!(AccountIndex::Age.name(), "age");
+        }
+
+        #[test]
+        fn it_reads_from_secondaries() {
+            // The driver needs a runtime to create a client, though nothing is connected to.
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let _rt = rt.enter();
+            let opts = ClientOptions::builder()
+                .hosts(vec![ServerAddress::parse("localhost:27017").unwrap()])
+                .build();
+            let db = mongodb::Client::with_options(opts)
+                .unwrap()
+                .database("test");
+            let accounts = AccountColl::new(&db);
+
+            assert!(matches!(
+                accounts.read_only().selection_criteria(),
+                Some(SelectionCriteria::ReadPreference(
+                    ReadPreference::SecondaryPreferred { .. }
+                ))
+            ));
+            let primary = SelectionCriteria::ReadPreference(ReadPreference::Primary);
+            assert!(matches!(
+                accounts.read_only_with(primary).selection_criteria(),
+                Some(SelectionCriteria::ReadPreference(ReadPreference::Primary))
+            ));
+        }
+
+        /// Records the query of every fetch, none of which completes.
+        #[derive(Default)]
+        struct Spy(Mutex<Vec<Query>>);
+
+        impl Collection for Spy {
+            type Internal = mongodb::Collection<Account>;
+            type Document = Account;
+
+            fn fetch<F: Fetcher<Account, Self::Internal>>(
+                &self,
+                f: F,
+            ) -> impl Future<Output = Result<F::Output, F::Error>> + Send {
+                self.0.lock().unwrap().extend(f.query());
+                future::pending()
+            }
+        }
+
+        #[test]
+        fn it_finds_active_records_by_unique_fields() {
+            let spy = Spy::default();
+            let mut cx = Context::from_waker(Waker::noop());
+
+            let find = pin!(Account::find_by_email(&spy, "ada@example.com"));
+            assert!(find.poll(&mut cx).is_pending());
+
+            let queries = spy.0.lock().unwrap();
+            assert_eq!(queries.len(), 1);
+            assert_eq!(queries[0].filter, doc! { "mail": "ada@example.com" });
+            assert_eq!(queries[0].limit, Some(1));
+        }
+    }
+}
+
 fn main() {}